@@ -0,0 +1,78 @@
+//! Throughput of `Document::parse` over a few large inputs. Parsing is
+//! linear, so `prose` and `prose_10mb` should run at about the same speed.
+//!
+//! Inputs with a `_parallel` suffix are parsed with `ParseOpts::parallel`.
+//!
+//! Run with `cargo bench`, pass a substring to run only matching inputs.
+use std::{
+  fs,
+  time::{Duration, Instant},
+};
+
+fn main() {
+  let filter = std::env::args().skip(1).find(|it| !it.starts_with('-')).unwrap_or_default();
+  for (name, text) in inputs() {
+    if !name.contains(&filter) {
+      continue;
+    }
+    let opts = djot::ParseOpts { parallel: name.ends_with("_parallel"), ..Default::default() };
+    let time = measure(|| djot::Document::parse_opts(opts.clone(), &text));
+    let mb_per_sec = text.len() as f64 / time.as_secs_f64() / 1e6;
+    println!("{name:<20} {:>8} KiB {:>10.2?} {mb_per_sec:>8.2} MB/s", text.len() / 1024, time);
+  }
+}
+
+/// Median time of a run, doing at least five runs and at least a second.
+fn measure<T>(mut f: impl FnMut() -> T) -> Duration {
+  let mut times = Vec::new();
+  let start = Instant::now();
+  while times.len() < 5 || start.elapsed() < Duration::from_secs(1) {
+    let t = Instant::now();
+    std::hint::black_box(f());
+    times.push(t.elapsed());
+  }
+  times.sort();
+  times[times.len() / 2]
+}
+
+const SIZE: usize = 1 << 20;
+
+fn inputs() -> Vec<(&'static str, String)> {
+  let mut spec = String::new();
+  let mut paths: Vec<_> =
+    fs::read_dir("./tests/data").unwrap().map(|it| it.unwrap().path()).collect();
+  paths.sort();
+  for path in paths {
+    if path.extension().unwrap_or_default() == "test" {
+      spec.push_str(&fs::read_to_string(&path).unwrap());
+      spec.push('\n');
+    }
+  }
+
+  let prose = "\
+Some *strong* and _emphasized_ text, with `verbatim`, a [link](https://example.com)
+and an ![image][ref]{.wide}. Also ~sub~ and ^sup^, {+inserted+}, {-deleted-} and
+{=marked=} text, \"quotes\", an :emoji: and some -- dashes --- here...
+
+[ref]: /img.png
+
+``` rust
+fn main() {}
+```
+
+";
+
+  let long_line = "words and *more* words, ".repeat(100) + "\n\n";
+
+  vec![
+    ("spec", repeat(&spec, SIZE)),
+    ("prose", repeat(prose, SIZE)),
+    ("prose_10mb", repeat(prose, 10 * SIZE)),
+    ("prose_10mb_parallel", repeat(prose, 10 * SIZE)),
+    ("long_lines", repeat(&long_line, SIZE)),
+  ]
+}
+
+fn repeat(text: &str, size: usize) -> String {
+  text.repeat(size / text.len() + 1)
+}
@@ -0,0 +1,61 @@
+//! Populates `./corpus` with the test cases from `../tests/data`:
+//!
+//!     cargo run --example seed_corpus
+//!     cargo fuzz run parse
+use std::{fs, path::Path};
+
+const TARGETS: &[&str] = &["parse", "to_html", "to_json", "attributes"];
+
+fn main() {
+  let fuzz_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let mut total = 0;
+  for entry in fs::read_dir(fuzz_dir.join("../tests/data")).unwrap() {
+    let path = entry.unwrap().path();
+    if path.extension().unwrap_or_default() != "test" {
+      continue;
+    }
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let source = fs::read_to_string(&path).unwrap();
+    for (i, djot) in test_inputs(&source).into_iter().enumerate() {
+      for target in TARGETS {
+        let dir = fuzz_dir.join("corpus").join(target);
+        fs::create_dir_all(&dir).unwrap();
+        let input = if *target == "attributes" { attributes(&djot) } else { Some(djot.as_str()) };
+        if let Some(input) = input {
+          fs::write(dir.join(format!("{stem}-{i}")), input).unwrap();
+        }
+      }
+      total += 1;
+    }
+  }
+  eprintln!("seeded {total} inputs");
+}
+
+/// The djot part of each case, see `parse_test` in `tests/spec.rs`.
+fn test_inputs(source: &str) -> Vec<String> {
+  let mut res = Vec::new();
+  let mut lines = source.lines();
+  while let Some(line) = lines.next() {
+    if line == "STOP" {
+      break;
+    }
+    if line.is_empty() || !line.bytes().all(|it| it == b'`') {
+      continue;
+    }
+    let fence = line;
+    let mut djot = String::new();
+    for line in lines.by_ref().take_while(|&it| it != ".") {
+      djot.push_str(line);
+      djot.push('\n');
+    }
+    lines.by_ref().take_while(|&it| it != fence).for_each(drop);
+    res.push(djot);
+  }
+  res
+}
+
+fn attributes(djot: &str) -> Option<&str> {
+  let start = djot.find('{')?;
+  let end = djot[start..].find('}').map_or(djot.len(), |it| start + it + 1);
+  Some(&djot[start..end])
+}
@@ -317,15 +317,22 @@ impl MatchState {
 
 
     fn patt_match(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
-        let mut s = s;
-        let mut p = p;
+        /* C Lua uses `goto init` for the tail calls below, which doesn't
+           nest, so restore the depth however we leave */
         self.matchdepth -= 1;
         if self.matchdepth == 0 {
             return error("pattern too complex");
         }
+        let res = self.patt_match_tail(s, p);
+        self.matchdepth += 1;
+        res
+    }
+
+    fn patt_match_tail(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
+        let mut s = s;
+        let mut p = p;
 
         if p == self.p_end {  /* end of pattern? */
-            self.matchdepth += 1;
             return Ok(s);
         }
         match at(p) {
@@ -382,7 +389,6 @@ impl MatchState {
             _ => return self.patt_default_match(s, p)
 
         }
-        self.matchdepth += 1;
         Ok(s)
     }
 
@@ -423,7 +429,6 @@ impl MatchState {
                 }
             }
         }
-        self.matchdepth += 1;
         Ok(s)
     }
 
@@ -2,7 +2,7 @@ mod generated;
 
 use std::fmt;
 
-pub(crate) use self::generated::*;
+pub use self::generated::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(crate) enum Annot {
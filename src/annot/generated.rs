@@ -1,12 +1,13 @@
 use std::fmt;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) enum Comp {
+pub enum Comp {
   Verbatim,
   Email,
   Url,
   Subscript,
   Superscript,
   Para,
+  Heading,
   CodeBlock,
   Imagetext,
   Linktext,
@@ -21,6 +22,7 @@ pub(crate) enum Comp {
   Delete,
   Mark,
   Attributes,
+  BlockAttributes,
 }
 
 impl fmt::Display for Comp {
@@ -32,6 +34,7 @@ impl fmt::Display for Comp {
       Comp::Subscript => "subscript",
       Comp::Superscript => "superscript",
       Comp::Para => "para",
+      Comp::Heading => "heading",
       Comp::CodeBlock => "code_block",
       Comp::Imagetext => "imagetext",
       Comp::Linktext => "linktext",
@@ -46,12 +49,13 @@ impl fmt::Display for Comp {
       Comp::Delete => "delete",
       Comp::Mark => "mark",
       Comp::Attributes => "attributes",
+      Comp::BlockAttributes => "block_attributes",
     })
   }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) enum Atom {
+pub enum Atom {
   Str,
   Escape,
   Hardbreak,
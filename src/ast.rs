@@ -1,3 +1,4 @@
+pub mod borrowed;
 mod generated;
 
 use indexmap::IndexMap;
@@ -6,9 +7,36 @@ pub use self::generated::*;
 
 pub type Attrs = IndexMap<String, String>;
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+/// Values of the front matter, see [`crate::Document::metadata`].
+pub type Metadata = IndexMap<String, serde_json::Value>;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ReferenceDefinition {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub destination: String,
 }
+
+/// Location of a node in the source text, see [`crate::ParseOpts::source_positions`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SourceSpan {
+  pub start: SourcePos,
+  pub end: SourcePos,
+}
+
+/// `line` and `col` are 1-based, `col` counts chars; `offset` is a byte offset.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SourcePos {
+  pub line: usize,
+  pub col: usize,
+  pub offset: usize,
+}
+
+/// The key of a reference in [`crate::Document::references`]: the label with
+/// each run of whitespace made a single space. Like in djot.lua, case matters:
+/// `[Foo]: /u` doesn't define `[foo][]`.
+pub fn normalize_label(label: &str) -> String {
+  label.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,78 @@
+//! The AST with text borrowed from the source where possible, see
+//! [`Document::parse`].
+mod generated;
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use indexmap::IndexMap;
+
+use crate::{
+  ast::{self, SourceSpan},
+  block, tree, ParseError, ParseOpts,
+};
+
+pub use self::generated::*;
+
+pub type Attrs<'s> = IndexMap<Cow<'s, str>, Cow<'s, str>>;
+
+pub(crate) fn attrs_into_owned(attrs: Attrs<'_>) -> ast::Attrs {
+  attrs.into_iter().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ReferenceDefinition<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub destination: Cow<'s, str>,
+}
+
+impl<'s> ReferenceDefinition<'s> {
+  pub fn into_owned(self) -> ast::ReferenceDefinition {
+    ast::ReferenceDefinition {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      destination: self.destination.into_owned(),
+    }
+  }
+}
+
+/// Like [`crate::Document`], but text borrows from the parsed source.
+#[derive(Debug, Default, Clone)]
+pub struct Document<'s> {
+  pub children: Vec<Tag<'s>>,
+  pub references: BTreeMap<Cow<'s, str>, ReferenceDefinition<'s>>,
+  pub metadata: ast::Metadata,
+  pub debug: String,
+}
+
+impl<'s> Document<'s> {
+  /// Never fails: constructs the parser can't handle are skipped.
+  pub fn parse(text: &'s str) -> Document<'s> {
+    Document::parse_opts(ParseOpts::default(), text)
+  }
+
+  pub fn parse_opts(opts: ParseOpts, text: &'s str) -> Document<'s> {
+    Document::build(opts, text).0
+  }
+
+  pub(crate) fn build(opts: ParseOpts, text: &'s str) -> (Document<'s>, Option<ParseError>) {
+    let mut p = block::Tokenizer::new(text, opts);
+    p.parse();
+    let tree = tree::build(p, text);
+    (tree.doc, tree.error)
+  }
+
+  pub fn into_owned(self) -> crate::Document {
+    crate::Document {
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+      references: self
+        .references
+        .into_iter()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect(),
+      metadata: self.metadata,
+      debug: self.debug,
+      ..crate::Document::default()
+    }
+  }
+}
@@ -0,0 +1,508 @@
+use std::borrow::Cow;
+
+use super::{attrs_into_owned, Attrs};
+use crate::ast::{self, SourceSpan};
+
+#[derive(Debug, Default, Clone)]
+pub struct Section<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Section<'s> {
+  pub fn into_owned(self) -> ast::Section {
+    ast::Section {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Heading<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+  pub level: u32,
+}
+
+impl<'s> Heading<'s> {
+  pub fn into_owned(self) -> ast::Heading {
+    ast::Heading {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+      level: self.level,
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Para<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Para<'s> {
+  pub fn into_owned(self) -> ast::Para {
+    ast::Para {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Link<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+  pub destination: Option<Cow<'s, str>>,
+  pub reference: Option<Cow<'s, str>>,
+}
+
+impl<'s> Link<'s> {
+  pub fn into_owned(self) -> ast::Link {
+    ast::Link {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+      destination: self.destination.map(Cow::into_owned),
+      reference: self.reference.map(Cow::into_owned),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Image<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+  pub destination: Option<Cow<'s, str>>,
+  pub reference: Option<Cow<'s, str>>,
+}
+
+impl<'s> Image<'s> {
+  pub fn into_owned(self) -> ast::Image {
+    ast::Image {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+      destination: self.destination.map(Cow::into_owned),
+      reference: self.reference.map(Cow::into_owned),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CodeBlock<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+  pub lang: Option<Cow<'s, str>>,
+  pub text: Cow<'s, str>,
+}
+
+impl<'s> CodeBlock<'s> {
+  pub fn into_owned(self) -> ast::CodeBlock {
+    ast::CodeBlock {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+      lang: self.lang.map(Cow::into_owned),
+      text: self.text.into_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Strong<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Strong<'s> {
+  pub fn into_owned(self) -> ast::Strong {
+    ast::Strong {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Emph<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Emph<'s> {
+  pub fn into_owned(self) -> ast::Emph {
+    ast::Emph {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Insert<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Insert<'s> {
+  pub fn into_owned(self) -> ast::Insert {
+    ast::Insert {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Delete<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Delete<'s> {
+  pub fn into_owned(self) -> ast::Delete {
+    ast::Delete {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Mark<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Mark<'s> {
+  pub fn into_owned(self) -> ast::Mark {
+    ast::Mark {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Superscript<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Superscript<'s> {
+  pub fn into_owned(self) -> ast::Superscript {
+    ast::Superscript {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Subscript<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Subscript<'s> {
+  pub fn into_owned(self) -> ast::Subscript {
+    ast::Subscript {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Span<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> Span<'s> {
+  pub fn into_owned(self) -> ast::Span {
+    ast::Span {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DoubleQuoted<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+}
+
+impl<'s> DoubleQuoted<'s> {
+  pub fn into_owned(self) -> ast::DoubleQuoted {
+    ast::DoubleQuoted {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Url<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag<'s>>,
+  pub destination: Cow<'s, str>,
+}
+
+impl<'s> Url<'s> {
+  pub fn into_owned(self) -> ast::Url {
+    ast::Url {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      children: self.children.into_iter().map(Tag::into_owned).collect(),
+      destination: self.destination.into_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SoftBreak<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+}
+
+impl<'s> SoftBreak<'s> {
+  pub fn into_owned(self) -> ast::SoftBreak {
+    ast::SoftBreak { attrs: attrs_into_owned(self.attrs), pos: self.pos }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct EmDash<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+}
+
+impl<'s> EmDash<'s> {
+  pub fn into_owned(self) -> ast::EmDash {
+    ast::EmDash { attrs: attrs_into_owned(self.attrs), pos: self.pos }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct EnDash<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+}
+
+impl<'s> EnDash<'s> {
+  pub fn into_owned(self) -> ast::EnDash {
+    ast::EnDash { attrs: attrs_into_owned(self.attrs), pos: self.pos }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Verbatim<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub text: Cow<'s, str>,
+}
+
+impl<'s> Verbatim<'s> {
+  pub fn into_owned(self) -> ast::Verbatim {
+    ast::Verbatim {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      text: self.text.into_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Str<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub text: Cow<'s, str>,
+}
+
+impl<'s> Str<'s> {
+  pub fn into_owned(self) -> ast::Str {
+    ast::Str { attrs: attrs_into_owned(self.attrs), pos: self.pos, text: self.text.into_owned() }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Emoji<'s> {
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  pub alias: Cow<'s, str>,
+}
+
+impl<'s> Emoji<'s> {
+  pub fn into_owned(self) -> ast::Emoji {
+    ast::Emoji {
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      alias: self.alias.into_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum Tag<'s> {
+  Section(Section<'s>),
+  Heading(Heading<'s>),
+  Para(Para<'s>),
+  Link(Link<'s>),
+  Image(Image<'s>),
+  CodeBlock(CodeBlock<'s>),
+  Strong(Strong<'s>),
+  Emph(Emph<'s>),
+  Insert(Insert<'s>),
+  Delete(Delete<'s>),
+  Mark(Mark<'s>),
+  Superscript(Superscript<'s>),
+  Subscript(Subscript<'s>),
+  Span(Span<'s>),
+  DoubleQuoted(DoubleQuoted<'s>),
+  Url(Url<'s>),
+  SoftBreak(SoftBreak<'s>),
+  EmDash(EmDash<'s>),
+  EnDash(EnDash<'s>),
+  Verbatim(Verbatim<'s>),
+  Str(Str<'s>),
+  Emoji(Emoji<'s>),
+}
+
+impl<'s> Tag<'s> {
+  pub fn into_owned(self) -> ast::Tag {
+    match self {
+      Tag::Section(it) => ast::Tag::Section(it.into_owned()),
+      Tag::Heading(it) => ast::Tag::Heading(it.into_owned()),
+      Tag::Para(it) => ast::Tag::Para(it.into_owned()),
+      Tag::Link(it) => ast::Tag::Link(it.into_owned()),
+      Tag::Image(it) => ast::Tag::Image(it.into_owned()),
+      Tag::CodeBlock(it) => ast::Tag::CodeBlock(it.into_owned()),
+      Tag::Strong(it) => ast::Tag::Strong(it.into_owned()),
+      Tag::Emph(it) => ast::Tag::Emph(it.into_owned()),
+      Tag::Insert(it) => ast::Tag::Insert(it.into_owned()),
+      Tag::Delete(it) => ast::Tag::Delete(it.into_owned()),
+      Tag::Mark(it) => ast::Tag::Mark(it.into_owned()),
+      Tag::Superscript(it) => ast::Tag::Superscript(it.into_owned()),
+      Tag::Subscript(it) => ast::Tag::Subscript(it.into_owned()),
+      Tag::Span(it) => ast::Tag::Span(it.into_owned()),
+      Tag::DoubleQuoted(it) => ast::Tag::DoubleQuoted(it.into_owned()),
+      Tag::Url(it) => ast::Tag::Url(it.into_owned()),
+      Tag::SoftBreak(it) => ast::Tag::SoftBreak(it.into_owned()),
+      Tag::EmDash(it) => ast::Tag::EmDash(it.into_owned()),
+      Tag::EnDash(it) => ast::Tag::EnDash(it.into_owned()),
+      Tag::Verbatim(it) => ast::Tag::Verbatim(it.into_owned()),
+      Tag::Str(it) => ast::Tag::Str(it.into_owned()),
+      Tag::Emoji(it) => ast::Tag::Emoji(it.into_owned()),
+    }
+  }
+  pub fn pos(&self) -> Option<SourceSpan> {
+    match self {
+      Tag::Section(it) => it.pos,
+      Tag::Heading(it) => it.pos,
+      Tag::Para(it) => it.pos,
+      Tag::Link(it) => it.pos,
+      Tag::Image(it) => it.pos,
+      Tag::CodeBlock(it) => it.pos,
+      Tag::Strong(it) => it.pos,
+      Tag::Emph(it) => it.pos,
+      Tag::Insert(it) => it.pos,
+      Tag::Delete(it) => it.pos,
+      Tag::Mark(it) => it.pos,
+      Tag::Superscript(it) => it.pos,
+      Tag::Subscript(it) => it.pos,
+      Tag::Span(it) => it.pos,
+      Tag::DoubleQuoted(it) => it.pos,
+      Tag::Url(it) => it.pos,
+      Tag::SoftBreak(it) => it.pos,
+      Tag::EmDash(it) => it.pos,
+      Tag::EnDash(it) => it.pos,
+      Tag::Verbatim(it) => it.pos,
+      Tag::Str(it) => it.pos,
+      Tag::Emoji(it) => it.pos,
+    }
+  }
+  pub fn set_pos(&mut self, pos: Option<SourceSpan>) {
+    match self {
+      Tag::Section(it) => it.pos = pos,
+      Tag::Heading(it) => it.pos = pos,
+      Tag::Para(it) => it.pos = pos,
+      Tag::Link(it) => it.pos = pos,
+      Tag::Image(it) => it.pos = pos,
+      Tag::CodeBlock(it) => it.pos = pos,
+      Tag::Strong(it) => it.pos = pos,
+      Tag::Emph(it) => it.pos = pos,
+      Tag::Insert(it) => it.pos = pos,
+      Tag::Delete(it) => it.pos = pos,
+      Tag::Mark(it) => it.pos = pos,
+      Tag::Superscript(it) => it.pos = pos,
+      Tag::Subscript(it) => it.pos = pos,
+      Tag::Span(it) => it.pos = pos,
+      Tag::DoubleQuoted(it) => it.pos = pos,
+      Tag::Url(it) => it.pos = pos,
+      Tag::SoftBreak(it) => it.pos = pos,
+      Tag::EmDash(it) => it.pos = pos,
+      Tag::EnDash(it) => it.pos = pos,
+      Tag::Verbatim(it) => it.pos = pos,
+      Tag::Str(it) => it.pos = pos,
+      Tag::Emoji(it) => it.pos = pos,
+    }
+  }
+  pub fn attrs_mut(&mut self) -> &mut Attrs<'s> {
+    match self {
+      Tag::Section(it) => &mut it.attrs,
+      Tag::Heading(it) => &mut it.attrs,
+      Tag::Para(it) => &mut it.attrs,
+      Tag::Link(it) => &mut it.attrs,
+      Tag::Image(it) => &mut it.attrs,
+      Tag::CodeBlock(it) => &mut it.attrs,
+      Tag::Strong(it) => &mut it.attrs,
+      Tag::Emph(it) => &mut it.attrs,
+      Tag::Insert(it) => &mut it.attrs,
+      Tag::Delete(it) => &mut it.attrs,
+      Tag::Mark(it) => &mut it.attrs,
+      Tag::Superscript(it) => &mut it.attrs,
+      Tag::Subscript(it) => &mut it.attrs,
+      Tag::Span(it) => &mut it.attrs,
+      Tag::DoubleQuoted(it) => &mut it.attrs,
+      Tag::Url(it) => &mut it.attrs,
+      Tag::SoftBreak(it) => &mut it.attrs,
+      Tag::EmDash(it) => &mut it.attrs,
+      Tag::EnDash(it) => &mut it.attrs,
+      Tag::Verbatim(it) => &mut it.attrs,
+      Tag::Str(it) => &mut it.attrs,
+      Tag::Emoji(it) => &mut it.attrs,
+    }
+  }
+}
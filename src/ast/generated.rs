@@ -1,160 +1,212 @@
-use super::Attrs;
+use super::{Attrs, SourceSpan};
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Section {
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
+  pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
+  pub children: Vec<Tag>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Heading {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
   pub level: u32,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Para {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Link {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
   pub destination: Option<String>,
   pub reference: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Image {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
   pub destination: Option<String>,
   pub reference: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CodeBlock {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
   pub lang: Option<String>,
   pub text: String,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Strong {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Emph {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Insert {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Delete {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Mark {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Superscript {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Subscript {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Span {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct DoubleQuoted {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Url {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
   pub destination: String,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SoftBreak {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EmDash {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EnDash {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Verbatim {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub text: String,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Str {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub text: String,
 }
 
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Emoji {
-  #[serde(skip_serializing_if = "Attrs::is_empty")]
+  #[serde(default, skip_serializing_if = "Attrs::is_empty")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub pos: Option<SourceSpan>,
   pub alias: String,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "tag", rename_all = "snake_case")]
 pub enum Tag {
+  Section(Section),
   Heading(Heading),
   Para(Para),
   Link(Link),
@@ -177,3 +229,375 @@ pub enum Tag {
   Str(Str),
   Emoji(Emoji),
 }
+
+impl Tag {
+  pub fn pos(&self) -> Option<SourceSpan> {
+    match self {
+      Tag::Section(it) => it.pos,
+      Tag::Heading(it) => it.pos,
+      Tag::Para(it) => it.pos,
+      Tag::Link(it) => it.pos,
+      Tag::Image(it) => it.pos,
+      Tag::CodeBlock(it) => it.pos,
+      Tag::Strong(it) => it.pos,
+      Tag::Emph(it) => it.pos,
+      Tag::Insert(it) => it.pos,
+      Tag::Delete(it) => it.pos,
+      Tag::Mark(it) => it.pos,
+      Tag::Superscript(it) => it.pos,
+      Tag::Subscript(it) => it.pos,
+      Tag::Span(it) => it.pos,
+      Tag::DoubleQuoted(it) => it.pos,
+      Tag::Url(it) => it.pos,
+      Tag::SoftBreak(it) => it.pos,
+      Tag::EmDash(it) => it.pos,
+      Tag::EnDash(it) => it.pos,
+      Tag::Verbatim(it) => it.pos,
+      Tag::Str(it) => it.pos,
+      Tag::Emoji(it) => it.pos,
+    }
+  }
+  pub fn set_pos(&mut self, pos: Option<SourceSpan>) {
+    match self {
+      Tag::Section(it) => it.pos = pos,
+      Tag::Heading(it) => it.pos = pos,
+      Tag::Para(it) => it.pos = pos,
+      Tag::Link(it) => it.pos = pos,
+      Tag::Image(it) => it.pos = pos,
+      Tag::CodeBlock(it) => it.pos = pos,
+      Tag::Strong(it) => it.pos = pos,
+      Tag::Emph(it) => it.pos = pos,
+      Tag::Insert(it) => it.pos = pos,
+      Tag::Delete(it) => it.pos = pos,
+      Tag::Mark(it) => it.pos = pos,
+      Tag::Superscript(it) => it.pos = pos,
+      Tag::Subscript(it) => it.pos = pos,
+      Tag::Span(it) => it.pos = pos,
+      Tag::DoubleQuoted(it) => it.pos = pos,
+      Tag::Url(it) => it.pos = pos,
+      Tag::SoftBreak(it) => it.pos = pos,
+      Tag::EmDash(it) => it.pos = pos,
+      Tag::EnDash(it) => it.pos = pos,
+      Tag::Verbatim(it) => it.pos = pos,
+      Tag::Str(it) => it.pos = pos,
+      Tag::Emoji(it) => it.pos = pos,
+    }
+  }
+  pub fn attrs_mut(&mut self) -> &mut Attrs {
+    match self {
+      Tag::Section(it) => &mut it.attrs,
+      Tag::Heading(it) => &mut it.attrs,
+      Tag::Para(it) => &mut it.attrs,
+      Tag::Link(it) => &mut it.attrs,
+      Tag::Image(it) => &mut it.attrs,
+      Tag::CodeBlock(it) => &mut it.attrs,
+      Tag::Strong(it) => &mut it.attrs,
+      Tag::Emph(it) => &mut it.attrs,
+      Tag::Insert(it) => &mut it.attrs,
+      Tag::Delete(it) => &mut it.attrs,
+      Tag::Mark(it) => &mut it.attrs,
+      Tag::Superscript(it) => &mut it.attrs,
+      Tag::Subscript(it) => &mut it.attrs,
+      Tag::Span(it) => &mut it.attrs,
+      Tag::DoubleQuoted(it) => &mut it.attrs,
+      Tag::Url(it) => &mut it.attrs,
+      Tag::SoftBreak(it) => &mut it.attrs,
+      Tag::EmDash(it) => &mut it.attrs,
+      Tag::EnDash(it) => &mut it.attrs,
+      Tag::Verbatim(it) => &mut it.attrs,
+      Tag::Str(it) => &mut it.attrs,
+      Tag::Emoji(it) => &mut it.attrs,
+    }
+  }
+}
+
+pub trait Visitor {
+  fn visit_tag(&mut self, tag: &Tag) {
+    match tag {
+      Tag::Section(it) => self.visit_section(it),
+      Tag::Heading(it) => self.visit_heading(it),
+      Tag::Para(it) => self.visit_para(it),
+      Tag::Link(it) => self.visit_link(it),
+      Tag::Image(it) => self.visit_image(it),
+      Tag::CodeBlock(it) => self.visit_code_block(it),
+      Tag::Strong(it) => self.visit_strong(it),
+      Tag::Emph(it) => self.visit_emph(it),
+      Tag::Insert(it) => self.visit_insert(it),
+      Tag::Delete(it) => self.visit_delete(it),
+      Tag::Mark(it) => self.visit_mark(it),
+      Tag::Superscript(it) => self.visit_superscript(it),
+      Tag::Subscript(it) => self.visit_subscript(it),
+      Tag::Span(it) => self.visit_span(it),
+      Tag::DoubleQuoted(it) => self.visit_double_quoted(it),
+      Tag::Url(it) => self.visit_url(it),
+      Tag::SoftBreak(it) => self.visit_soft_break(it),
+      Tag::EmDash(it) => self.visit_em_dash(it),
+      Tag::EnDash(it) => self.visit_en_dash(it),
+      Tag::Verbatim(it) => self.visit_verbatim(it),
+      Tag::Str(it) => self.visit_str(it),
+      Tag::Emoji(it) => self.visit_emoji(it),
+    }
+  }
+  fn visit_children(&mut self, children: &[Tag]) {
+    for child in children {
+      self.visit_tag(child)
+    }
+  }
+  fn visit_section(&mut self, it: &Section) {
+    self.visit_children(&it.children)
+  }
+  fn visit_heading(&mut self, it: &Heading) {
+    self.visit_children(&it.children)
+  }
+  fn visit_para(&mut self, it: &Para) {
+    self.visit_children(&it.children)
+  }
+  fn visit_link(&mut self, it: &Link) {
+    self.visit_children(&it.children)
+  }
+  fn visit_image(&mut self, it: &Image) {
+    self.visit_children(&it.children)
+  }
+  fn visit_code_block(&mut self, it: &CodeBlock) {
+    self.visit_children(&it.children)
+  }
+  fn visit_strong(&mut self, it: &Strong) {
+    self.visit_children(&it.children)
+  }
+  fn visit_emph(&mut self, it: &Emph) {
+    self.visit_children(&it.children)
+  }
+  fn visit_insert(&mut self, it: &Insert) {
+    self.visit_children(&it.children)
+  }
+  fn visit_delete(&mut self, it: &Delete) {
+    self.visit_children(&it.children)
+  }
+  fn visit_mark(&mut self, it: &Mark) {
+    self.visit_children(&it.children)
+  }
+  fn visit_superscript(&mut self, it: &Superscript) {
+    self.visit_children(&it.children)
+  }
+  fn visit_subscript(&mut self, it: &Subscript) {
+    self.visit_children(&it.children)
+  }
+  fn visit_span(&mut self, it: &Span) {
+    self.visit_children(&it.children)
+  }
+  fn visit_double_quoted(&mut self, it: &DoubleQuoted) {
+    self.visit_children(&it.children)
+  }
+  fn visit_url(&mut self, it: &Url) {
+    self.visit_children(&it.children)
+  }
+  fn visit_soft_break(&mut self, _it: &SoftBreak) {}
+  fn visit_em_dash(&mut self, _it: &EmDash) {}
+  fn visit_en_dash(&mut self, _it: &EnDash) {}
+  fn visit_verbatim(&mut self, _it: &Verbatim) {}
+  fn visit_str(&mut self, _it: &Str) {}
+  fn visit_emoji(&mut self, _it: &Emoji) {}
+}
+
+pub trait VisitorMut {
+  fn visit_tag_mut(&mut self, tag: &mut Tag) {
+    match tag {
+      Tag::Section(it) => self.visit_section_mut(it),
+      Tag::Heading(it) => self.visit_heading_mut(it),
+      Tag::Para(it) => self.visit_para_mut(it),
+      Tag::Link(it) => self.visit_link_mut(it),
+      Tag::Image(it) => self.visit_image_mut(it),
+      Tag::CodeBlock(it) => self.visit_code_block_mut(it),
+      Tag::Strong(it) => self.visit_strong_mut(it),
+      Tag::Emph(it) => self.visit_emph_mut(it),
+      Tag::Insert(it) => self.visit_insert_mut(it),
+      Tag::Delete(it) => self.visit_delete_mut(it),
+      Tag::Mark(it) => self.visit_mark_mut(it),
+      Tag::Superscript(it) => self.visit_superscript_mut(it),
+      Tag::Subscript(it) => self.visit_subscript_mut(it),
+      Tag::Span(it) => self.visit_span_mut(it),
+      Tag::DoubleQuoted(it) => self.visit_double_quoted_mut(it),
+      Tag::Url(it) => self.visit_url_mut(it),
+      Tag::SoftBreak(it) => self.visit_soft_break_mut(it),
+      Tag::EmDash(it) => self.visit_em_dash_mut(it),
+      Tag::EnDash(it) => self.visit_en_dash_mut(it),
+      Tag::Verbatim(it) => self.visit_verbatim_mut(it),
+      Tag::Str(it) => self.visit_str_mut(it),
+      Tag::Emoji(it) => self.visit_emoji_mut(it),
+    }
+  }
+  fn visit_children_mut(&mut self, children: &mut [Tag]) {
+    for child in children {
+      self.visit_tag_mut(child)
+    }
+  }
+  fn visit_section_mut(&mut self, it: &mut Section) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_heading_mut(&mut self, it: &mut Heading) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_para_mut(&mut self, it: &mut Para) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_link_mut(&mut self, it: &mut Link) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_image_mut(&mut self, it: &mut Image) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_code_block_mut(&mut self, it: &mut CodeBlock) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_strong_mut(&mut self, it: &mut Strong) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_emph_mut(&mut self, it: &mut Emph) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_insert_mut(&mut self, it: &mut Insert) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_delete_mut(&mut self, it: &mut Delete) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_mark_mut(&mut self, it: &mut Mark) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_superscript_mut(&mut self, it: &mut Superscript) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_subscript_mut(&mut self, it: &mut Subscript) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_span_mut(&mut self, it: &mut Span) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_double_quoted_mut(&mut self, it: &mut DoubleQuoted) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_url_mut(&mut self, it: &mut Url) {
+    self.visit_children_mut(&mut it.children)
+  }
+  fn visit_soft_break_mut(&mut self, _it: &mut SoftBreak) {}
+  fn visit_em_dash_mut(&mut self, _it: &mut EmDash) {}
+  fn visit_en_dash_mut(&mut self, _it: &mut EnDash) {}
+  fn visit_verbatim_mut(&mut self, _it: &mut Verbatim) {}
+  fn visit_str_mut(&mut self, _it: &mut Str) {}
+  fn visit_emoji_mut(&mut self, _it: &mut Emoji) {}
+}
+
+pub trait Fold {
+  fn fold_tag(&mut self, tag: Tag) -> Tag {
+    match tag {
+      Tag::Section(it) => self.fold_section(it),
+      Tag::Heading(it) => self.fold_heading(it),
+      Tag::Para(it) => self.fold_para(it),
+      Tag::Link(it) => self.fold_link(it),
+      Tag::Image(it) => self.fold_image(it),
+      Tag::CodeBlock(it) => self.fold_code_block(it),
+      Tag::Strong(it) => self.fold_strong(it),
+      Tag::Emph(it) => self.fold_emph(it),
+      Tag::Insert(it) => self.fold_insert(it),
+      Tag::Delete(it) => self.fold_delete(it),
+      Tag::Mark(it) => self.fold_mark(it),
+      Tag::Superscript(it) => self.fold_superscript(it),
+      Tag::Subscript(it) => self.fold_subscript(it),
+      Tag::Span(it) => self.fold_span(it),
+      Tag::DoubleQuoted(it) => self.fold_double_quoted(it),
+      Tag::Url(it) => self.fold_url(it),
+      Tag::SoftBreak(it) => self.fold_soft_break(it),
+      Tag::EmDash(it) => self.fold_em_dash(it),
+      Tag::EnDash(it) => self.fold_en_dash(it),
+      Tag::Verbatim(it) => self.fold_verbatim(it),
+      Tag::Str(it) => self.fold_str(it),
+      Tag::Emoji(it) => self.fold_emoji(it),
+    }
+  }
+  fn fold_children(&mut self, children: Vec<Tag>) -> Vec<Tag> {
+    children.into_iter().map(|it| self.fold_tag(it)).collect()
+  }
+
+  fn fold_section(&mut self, mut it: Section) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Section(it)
+  }
+  fn fold_heading(&mut self, mut it: Heading) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Heading(it)
+  }
+  fn fold_para(&mut self, mut it: Para) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Para(it)
+  }
+  fn fold_link(&mut self, mut it: Link) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Link(it)
+  }
+  fn fold_image(&mut self, mut it: Image) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Image(it)
+  }
+  fn fold_code_block(&mut self, mut it: CodeBlock) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::CodeBlock(it)
+  }
+  fn fold_strong(&mut self, mut it: Strong) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Strong(it)
+  }
+  fn fold_emph(&mut self, mut it: Emph) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Emph(it)
+  }
+  fn fold_insert(&mut self, mut it: Insert) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Insert(it)
+  }
+  fn fold_delete(&mut self, mut it: Delete) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Delete(it)
+  }
+  fn fold_mark(&mut self, mut it: Mark) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Mark(it)
+  }
+  fn fold_superscript(&mut self, mut it: Superscript) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Superscript(it)
+  }
+  fn fold_subscript(&mut self, mut it: Subscript) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Subscript(it)
+  }
+  fn fold_span(&mut self, mut it: Span) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Span(it)
+  }
+  fn fold_double_quoted(&mut self, mut it: DoubleQuoted) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::DoubleQuoted(it)
+  }
+  fn fold_url(&mut self, mut it: Url) -> Tag {
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::Url(it)
+  }
+  fn fold_soft_break(&mut self, it: SoftBreak) -> Tag {
+    Tag::SoftBreak(it)
+  }
+  fn fold_em_dash(&mut self, it: EmDash) -> Tag {
+    Tag::EmDash(it)
+  }
+  fn fold_en_dash(&mut self, it: EnDash) -> Tag {
+    Tag::EnDash(it)
+  }
+  fn fold_verbatim(&mut self, it: Verbatim) -> Tag {
+    Tag::Verbatim(it)
+  }
+  fn fold_str(&mut self, it: Str) -> Tag {
+    Tag::Str(it)
+  }
+  fn fold_emoji(&mut self, it: Emoji) -> Tag {
+    Tag::Emoji(it)
+  }
+}
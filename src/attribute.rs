@@ -1,14 +1,41 @@
-use std::ops::Range;
+use std::{borrow::Cow, ops::Range, sync::Arc};
 
 use crate::{
   annot::{Annot, Atom},
-  patterns::find_at,
+  ast::borrowed::Attrs,
+  patterns::{find_at, pat},
   Match,
 };
 
+/// Collects the `id`, `class` and `key` matches between `+attributes` and
+/// `-attributes` into `Attrs`.
+pub(crate) fn collect_attrs<'s>(subject: &'s str, matches: &[Match]) -> Attrs<'s> {
+  let mut res = Attrs::new();
+  let mut matches = matches.iter();
+  while let Some(m) = matches.next() {
+    if m.is(Atom::Class) {
+      match res.entry(Cow::Borrowed("class")) {
+        indexmap::map::Entry::Occupied(mut it) => {
+          it.insert(Cow::Owned(format!("{} {}", it.get(), &subject[m.range.clone()])));
+        }
+        indexmap::map::Entry::Vacant(it) => {
+          it.insert(Cow::Borrowed(&subject[m.range.clone()]));
+        }
+      }
+    } else if m.is(Atom::Id) {
+      res.insert(Cow::Borrowed("id"), Cow::Borrowed(&subject[m.range.clone()]));
+    } else if m.is(Atom::Key) {
+      let key = &subject[m.range.clone()];
+      let value = matches.next().map_or("", |m| &subject[m.range.clone()]);
+      res.insert(Cow::Borrowed(key), Cow::Borrowed(value));
+    }
+  }
+  res
+}
+
 #[derive(Default)]
 pub(crate) struct Tokenizer {
-  subject: String,
+  subject: Arc<str>,
   state: State,
   begin: usize,
   lastpos: usize,
@@ -39,7 +66,7 @@ pub(crate) enum Status {
 }
 
 impl Tokenizer {
-  pub(crate) fn new(subject: String) -> Tokenizer {
+  pub(crate) fn new(subject: Arc<str>) -> Tokenizer {
     let mut res = Tokenizer::default();
     res.subject = subject;
     res
@@ -81,7 +108,7 @@ impl Tokenizer {
   fn step(&mut self, pos: usize) -> State {
     match self.state {
       State::Start => {
-        if find_at(&self.subject, "^{", pos).is_match {
+        if find_at(&self.subject, pat!("^{"), pos).is_match {
           State::Scanning
         } else {
           State::Fail
@@ -105,7 +132,7 @@ impl Tokenizer {
           State::ScanningClass
         }
         _ => {
-          if find_at(&self.subject, "^[%a%d_:-]", pos).is_match {
+          if find_at(&self.subject, pat!("^[%a%d_:-]"), pos).is_match {
             self.begin = pos;
             State::ScanningKey
           } else {
@@ -128,7 +155,7 @@ impl Tokenizer {
           self.add_match(self.begin..self.lastpos, Atom::Key);
           self.begin = !0;
           State::ScanningValue
-        } else if find_at(&self.subject, "^[%a%d_:-]", pos).is_match {
+        } else if find_at(&self.subject, pat!("^[%a%d_:-]"), pos).is_match {
           State::ScanningKey
         } else {
           State::Fail
@@ -139,7 +166,7 @@ impl Tokenizer {
         if c == b'"' {
           self.begin = pos;
           State::ScanningQuotedValue
-        } else if find_at(&self.subject, "^[%a%d_:-]", pos).is_match {
+        } else if find_at(&self.subject, pat!("^[%a%d_:-]"), pos).is_match {
           self.begin = pos;
           State::ScanningBareValue
         } else {
@@ -148,13 +175,13 @@ impl Tokenizer {
       }
       State::ScanningBareValue => {
         let c = self.subject.as_bytes()[pos];
-        if find_at(&self.subject, "^[%a%d_:-]", pos).is_match {
+        if find_at(&self.subject, pat!("^[%a%d_:-]"), pos).is_match {
           State::ScanningBareValue
         } else if c == b'}' {
           self.add_match(self.begin..self.lastpos, Atom::Value);
           self.begin = !0;
           State::Done
-        } else if find_at(&self.subject, "^%s", pos).is_match {
+        } else if find_at(&self.subject, pat!("^%s"), pos).is_match {
           self.add_match(self.begin..self.lastpos, Atom::Value);
           self.begin = !0;
           State::Scanning
@@ -195,9 +222,9 @@ impl Tokenizer {
         State::Done
       }
       _ => {
-        if find_at(&self.subject, "^[^%s%p]", pos).is_match {
+        if find_at(&self.subject, pat!("^[^%s%p]"), pos).is_match {
           state
-        } else if find_at(&self.subject, "^%s", pos).is_match {
+        } else if find_at(&self.subject, pat!("^%s"), pos).is_match {
           if self.lastpos > self.begin {
             self.add_match(self.begin + 1..self.lastpos, atom)
           }
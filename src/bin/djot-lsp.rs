@@ -0,0 +1,404 @@
+//! A language server for djot, speaking LSP over stdio.
+//!
+//! It publishes [`djot::Document::diagnostics`], lists the headings as
+//! document symbols, goes from a link to the definition of its reference,
+//! completes reference labels and emoji aliases, and previews the HTML of the
+//! block under the cursor on hover. Documents sync incrementally, each edit
+//! goes to [`djot::Document::reparse`].
+use std::{
+  collections::HashMap,
+  io::{BufRead, Write},
+  process::ExitCode,
+};
+
+use anyhow::{bail, Context};
+use djot::ast::{self, SourceSpan, Tag, Visitor};
+use serde_json::{json, Value};
+
+fn main() -> anyhow::Result<ExitCode> {
+  let mut stdin = std::io::stdin().lock();
+  let mut stdout = std::io::stdout().lock();
+  let mut server = Server::default();
+  while let Some(message) = read_message(&mut stdin)? {
+    for reply in server.handle(message) {
+      write_message(&mut stdout, &reply)?;
+    }
+    if server.exit {
+      break;
+    }
+  }
+  // Exiting without a shutdown request is an error.
+  Ok(if server.shutdown { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Reads a message with its `Content-Length` header, `None` at the end of
+/// the input.
+fn read_message(input: &mut impl BufRead) -> anyhow::Result<Option<Value>> {
+  let mut len = None;
+  loop {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    if let Some(value) = line.strip_prefix("Content-Length:") {
+      len = Some(value.trim().parse::<usize>().context("invalid Content-Length")?);
+    }
+  }
+  let Some(len) = len else { bail!("missing Content-Length") };
+  let mut buf = vec![0; len];
+  input.read_exact(&mut buf)?;
+  Ok(Some(serde_json::from_slice(&buf).context("invalid message")?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> anyhow::Result<()> {
+  let json = message.to_string();
+  write!(output, "Content-Length: {}\r\n\r\n{json}", json.len())?;
+  output.flush()?;
+  Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+  files: HashMap<String, File>,
+  shutdown: bool,
+  exit: bool,
+}
+
+/// An open document.
+struct File {
+  text: String,
+  doc: djot::Document,
+}
+
+impl File {
+  fn new(text: String) -> File {
+    let opts = djot::ParseOpts { source_positions: true, ..djot::ParseOpts::default() };
+    let doc = djot::Document::parse_opts(opts, &text);
+    File { text, doc }
+  }
+}
+
+impl Server {
+  /// Handles a request or notification, returning the messages to send.
+  fn handle(&mut self, message: Value) -> Vec<Value> {
+    let method = message["method"].as_str().unwrap_or_default();
+    let params = &message["params"];
+    let Some(id) = message.get("id").cloned() else {
+      return self.notification(method, params).unwrap_or_else(|err| {
+        eprintln!("djot-lsp: {method}: {err:#}");
+        Vec::new()
+      });
+    };
+    let res = match method {
+      // A response, we send no requests.
+      "" => return Vec::new(),
+      "initialize" => Ok(json!({
+        "capabilities": {
+          "textDocumentSync": { "openClose": true, "change": 2 },
+          "documentSymbolProvider": true,
+          "definitionProvider": true,
+          "completionProvider": { "triggerCharacters": ["[", ":"] },
+          "hoverProvider": true,
+        },
+        "serverInfo": { "name": "djot-lsp" },
+      })),
+      "shutdown" => {
+        self.shutdown = true;
+        Ok(Value::Null)
+      }
+      "textDocument/documentSymbol" => {
+        self.file(params).map(|file| json!(symbols(file, &file.doc.children)))
+      }
+      "textDocument/definition" => self
+        .at(params)
+        .map(|(file, offset)| definition(file, &params["textDocument"]["uri"], offset)),
+      "textDocument/completion" => self.at(params).map(|(file, offset)| completion(file, offset)),
+      "textDocument/hover" => self.at(params).map(|(file, offset)| hover(file, offset)),
+      _ => {
+        let error = json!({ "code": -32601, "message": format!("unknown method {method}") });
+        return vec![json!({ "jsonrpc": "2.0", "id": id, "error": error })];
+      }
+    };
+    let reply = match res {
+      Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+      Err(err) => {
+        let error = json!({ "code": -32602, "message": format!("{err:#}") });
+        json!({ "jsonrpc": "2.0", "id": id, "error": error })
+      }
+    };
+    vec![reply]
+  }
+
+  fn notification(&mut self, method: &str, params: &Value) -> anyhow::Result<Vec<Value>> {
+    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+    match method {
+      "exit" => self.exit = true,
+      "textDocument/didOpen" => {
+        let text = params["textDocument"]["text"].as_str().context("missing text")?;
+        self.files.insert(uri.clone(), File::new(text.to_string()));
+        return Ok(vec![self.publish_diagnostics(&uri)]);
+      }
+      "textDocument/didChange" => {
+        let file = self.files.get_mut(&uri).context("unknown document")?;
+        let changes = params["contentChanges"].as_array().context("missing changes")?;
+        for change in changes {
+          let text = change["text"].as_str().context("missing text")?;
+          let range = &change["range"];
+          if range.is_null() {
+            *file = File::new(text.to_string());
+            continue;
+          }
+          let edit = offset(&file.text, &range["start"])?..offset(&file.text, &range["end"])?;
+          file.doc.reparse(edit.clone(), text);
+          file.text.replace_range(edit, text);
+        }
+        return Ok(vec![self.publish_diagnostics(&uri)]);
+      }
+      "textDocument/didClose" => {
+        self.files.remove(&uri);
+        return Ok(vec![self.publish_diagnostics(&uri)]);
+      }
+      _ => (),
+    }
+    Ok(Vec::new())
+  }
+
+  fn publish_diagnostics(&self, uri: &str) -> Value {
+    let diagnostics: Vec<Value> = match self.files.get(uri) {
+      Some(file) => file
+        .doc
+        .diagnostics()
+        .into_iter()
+        .map(|it| {
+          let severity = match it.severity {
+            djot::Severity::Error => 1,
+            djot::Severity::Warning => 2,
+            djot::Severity::Hint => 4,
+          };
+          json!({
+            "range": range(&file.text, Some(it.span)),
+            "severity": severity,
+            "source": "djot",
+            "message": it.message,
+          })
+        })
+        .collect(),
+      None => Vec::new(),
+    };
+    json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/publishDiagnostics",
+      "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+  }
+
+  fn file(&self, params: &Value) -> anyhow::Result<&File> {
+    let uri = params["textDocument"]["uri"].as_str().context("missing uri")?;
+    self.files.get(uri).with_context(|| format!("unknown document {uri}"))
+  }
+
+  /// The file and byte offset of a text document position.
+  fn at(&self, params: &Value) -> anyhow::Result<(&File, usize)> {
+    let file = self.file(params)?;
+    Ok((file, offset(&file.text, &params["position"])?))
+  }
+}
+
+/// Nested symbols for the sections.
+fn symbols(file: &File, children: &[Tag]) -> Vec<Value> {
+  let mut res = Vec::new();
+  for child in children {
+    let Tag::Section(section) = child else { continue };
+    let Some(Tag::Heading(heading)) = section.children.first() else {
+      res.extend(symbols(file, &section.children));
+      continue;
+    };
+    let mut name = PlainText::default();
+    name.visit_children(&heading.children);
+    let name = name.0.trim();
+    res.push(json!({
+      "name": if name.is_empty() { "#" } else { name },
+      "detail": format!("h{}", heading.level),
+      // String, as markdown servers do.
+      "kind": 15,
+      "range": range(&file.text, section.pos),
+      "selectionRange": range(&file.text, heading.pos),
+      "children": symbols(file, &section.children),
+    }))
+  }
+  res
+}
+
+/// The definition of the reference of the link or image at `offset`, or the
+/// heading defining it.
+fn definition(file: &File, uri: &Value, offset: usize) -> Value {
+  let mut finder = ReferenceAt { offset, reference: None };
+  finder.visit_children(&file.doc.children);
+  let Some(reference) = finder.reference else { return Value::Null };
+  let Some(reference_definition) = file.doc.references.get(&ast::normalize_label(&reference))
+  else {
+    return Value::Null;
+  };
+  let pos = match reference_definition.pos {
+    Some(pos) => Some(pos),
+    None => reference_definition
+      .destination
+      .strip_prefix('#')
+      .and_then(|id| heading_pos(&file.doc.children, id)),
+  };
+  match pos {
+    Some(pos) => json!({ "uri": uri, "range": range(&file.text, Some(pos)) }),
+    None => Value::Null,
+  }
+}
+
+fn heading_pos(children: &[Tag], id: &str) -> Option<SourceSpan> {
+  children.iter().find_map(|child| {
+    let Tag::Section(section) = child else { return None };
+    match section.children.first() {
+      Some(Tag::Heading(heading)) if section.attrs.get("id").is_some_and(|it| it == id) => {
+        heading.pos
+      }
+      _ => heading_pos(&section.children, id),
+    }
+  })
+}
+
+/// Emoji aliases after `:`, reference labels after `][`.
+fn completion(file: &File, offset: usize) -> Value {
+  let line_start = file.text[..offset].rfind('\n').map_or(0, |it| it + 1);
+  let before = &file.text[line_start..offset];
+  let edit = |start: usize, new_text: &str| {
+    let start = position(&file.text, line_start + start);
+    json!({ "range": { "start": start, "end": position(&file.text, offset) }, "newText": new_text })
+  };
+  let mut items = Vec::new();
+  if let Some(colon) = before.rfind(':') {
+    let prefix = &before[colon + 1..];
+    if prefix.chars().all(|it| it.is_ascii_alphanumeric() || "_+-".contains(it)) {
+      for &(alias, emoji) in djot::emoji::EMOJI_LIST {
+        if alias.starts_with(prefix) {
+          let text_edit = edit(colon + 1, &format!("{alias}:"));
+          items.push(json!({ "label": alias, "kind": 21, "detail": emoji, "textEdit": text_edit }));
+        }
+      }
+    }
+  }
+  if let Some(open) = before.rfind('[') {
+    let prefix = &before[open + 1..];
+    if before[..open].ends_with(']') && !prefix.contains(']') {
+      for (label, reference_definition) in &file.doc.references {
+        if label.starts_with(prefix) {
+          let detail = &reference_definition.destination;
+          let text_edit = edit(open + 1, label);
+          items
+            .push(json!({ "label": label, "kind": 18, "detail": detail, "textEdit": text_edit }));
+        }
+      }
+    }
+  }
+  json!(items)
+}
+
+/// The HTML of the innermost block at `offset`.
+fn hover(file: &File, offset: usize) -> Value {
+  let mut children = &file.doc.children;
+  loop {
+    let Some(block) = children.iter().find(|it| contains(it.pos(), offset)) else {
+      return Value::Null;
+    };
+    if let Tag::Section(section) = block {
+      children = &section.children;
+      continue;
+    }
+    let mut preview = file.doc.clone();
+    preview.children = vec![block.clone()];
+    let html = preview.to_html();
+    return json!({
+      "contents": { "kind": "markdown", "value": format!("```html\n{html}```") },
+      "range": range(&file.text, block.pos()),
+    });
+  }
+}
+
+/// Finds the innermost link or image at `offset` with a reference.
+struct ReferenceAt {
+  offset: usize,
+  reference: Option<String>,
+}
+
+impl Visitor for ReferenceAt {
+  fn visit_link(&mut self, it: &ast::Link) {
+    if it.destination.is_none() && contains(it.pos, self.offset) {
+      self.reference = it.reference.clone();
+    }
+    self.visit_children(&it.children)
+  }
+  fn visit_image(&mut self, it: &ast::Image) {
+    if it.destination.is_none() && contains(it.pos, self.offset) {
+      self.reference = it.reference.clone();
+    }
+    self.visit_children(&it.children)
+  }
+}
+
+#[derive(Default)]
+struct PlainText(String);
+
+impl Visitor for PlainText {
+  fn visit_soft_break(&mut self, _it: &ast::SoftBreak) {
+    self.0.push(' ')
+  }
+  fn visit_verbatim(&mut self, it: &ast::Verbatim) {
+    self.0.push_str(&it.text)
+  }
+  fn visit_str(&mut self, it: &ast::Str) {
+    self.0.push_str(&it.text)
+  }
+}
+
+fn contains(span: Option<SourceSpan>, offset: usize) -> bool {
+  span.is_some_and(|it| it.start.offset <= offset && offset <= it.end.offset)
+}
+
+/// The byte offset of an LSP position, whose `character` counts UTF-16 code
+/// units. Positions past the end of a line are at its end.
+fn offset(text: &str, position: &Value) -> anyhow::Result<usize> {
+  let (Some(line), Some(character)) = (position["line"].as_u64(), position["character"].as_u64())
+  else {
+    bail!("invalid position {position}")
+  };
+  let mut line_start = 0;
+  for _ in 0..line {
+    match text[line_start..].find('\n') {
+      Some(idx) => line_start += idx + 1,
+      None => return Ok(text.len()),
+    }
+  }
+  let line = text[line_start..].split('\n').next().unwrap_or_default();
+  let mut units = 0;
+  for (idx, c) in line.char_indices() {
+    if units >= character as usize {
+      return Ok(line_start + idx);
+    }
+    units += c.len_utf16();
+  }
+  Ok(line_start + line.len())
+}
+
+fn position(text: &str, offset: usize) -> Value {
+  let offset = offset.min(text.len());
+  let line_start = text[..offset].rfind('\n').map_or(0, |it| it + 1);
+  let line = text[..line_start].matches('\n').count();
+  let character = text[line_start..offset].encode_utf16().count();
+  json!({ "line": line, "character": character })
+}
+
+fn range(text: &str, span: Option<SourceSpan>) -> Value {
+  let span = span.unwrap_or_default();
+  json!({ "start": position(text, span.start.offset), "end": position(text, span.end.offset) })
+}
@@ -1,31 +1,56 @@
-use std::ops::Range;
+use std::{
+  ops::Range,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  thread,
+};
 
 use crate::{
   annot::{Annot, Atom, Comp},
-  format_to, inline,
-  patterns::{find, find_at, PatMatch},
+  ast::Metadata,
+  attribute,
+  diagnostics::Warning,
+  format_to, inline, metadata,
+  patterns::{find, find_at, pat, PatMatch, Pattern},
   Match, ParseOpts,
 };
 
 #[derive(Default)]
 pub struct Tokenizer {
-  pub subject: String,
+  pub subject: Arc<str>,
   indent: usize,
   startline: usize,
   starteol: usize,
   endeol: usize,
   pub(crate) matches: Vec<Match>,
-  pos: usize,
+  pub(crate) pos: usize,
   last_matched_container: usize,
-  opts: ParseOpts,
+  pub(crate) opts: ParseOpts,
   finished_line: bool,
+  /// Line starts with no open container: the input before and after such a
+  /// line parses independently.
+  pub(crate) block_starts: Vec<usize>,
+  /// Sorted block starts at which to stop, leaving the rest unparsed.
+  pub(crate) resync: Vec<usize>,
+  /// Paragraphs left for `tokenize_inlines`, see [`ParseOpts::parallel`].
+  inline_jobs: Vec<InlineJob>,
+  /// Block attributes were closed, and the block they belong to hasn't
+  /// started yet.
+  attributes_pending: bool,
+  /// End of the front matter, or 0 if there is none.
+  pub(crate) front_matter: usize,
+  pub(crate) metadata: Metadata,
+  /// Problems which don't stop parsing, for [`crate::Document::diagnostics`].
+  pub(crate) warnings: Vec<Warning>,
 
   pub(crate) debug: String,
 }
 
 trait Container {
   fn content(&self) -> &'static str;
-  fn inline_parser(&mut self) -> Option<&mut inline::Tokenizer> {
+  fn inline_lines(&mut self) -> Option<&mut Vec<Range<usize>>> {
     None
   }
   fn restore_indent(&self) -> Option<usize> {
@@ -39,38 +64,114 @@ trait Container {
 }
 
 const CONTAINERS: &[fn(&mut Tokenizer, &mut Vec<Box<dyn Container>>) -> bool] =
-  &[Para::open, CodeBlock::open, ReferenceDefinition::open];
+  &[Para::open, Heading::open, CodeBlock::open, ReferenceDefinition::open, BlockAttributes::open];
 
+/// Inline content is tokenized once the paragraph closes, on another thread
+/// with [`ParseOpts::parallel`].
 struct Para {
-  inline_parser: inline::Tokenizer,
+  inline: InlineJob,
+}
+
+/// Lines of a paragraph or heading, whose inline matches go before
+/// `matches[idx]`.
+struct InlineJob {
+  idx: usize,
+  lines: Vec<Range<usize>>,
+  opts: ParseOpts,
+  /// A container closed right after the last inline match, or at the offset
+  /// if there is none.
+  close: Option<(Comp, usize)>,
+}
+
+impl InlineJob {
+  fn new(p: &Tokenizer) -> InlineJob {
+    // The inline parser gets whatever is left of the match budget.
+    let mut opts = p.opts.clone();
+    opts.max_matches = opts.max_matches.saturating_sub(p.matches.len());
+    InlineJob { idx: 0, lines: Vec::new(), opts, close: None }
+  }
+
+  fn tokenize(&self, subject: &Arc<str>) -> (Vec<Match>, Vec<Warning>) {
+    let mut inline_parser = inline::Tokenizer::new(Arc::clone(subject), self.opts.clone());
+    for line in &self.lines {
+      inline_parser.feed(line.start, line.end)
+    }
+    let mut res = inline_parser.get_matches();
+    if let Some((comp, start)) = self.close {
+      let end = res.last().map_or(start, |it| it.range.end);
+      res.push(Match::new(end..end, comp.sub()));
+    }
+    (res, inline_parser.warnings)
+  }
 }
 
 impl Container for Para {
   fn content(&self) -> &'static str {
     "inline"
   }
-  fn inline_parser(&mut self) -> Option<&mut inline::Tokenizer> {
-    Some(&mut self.inline_parser)
+  fn inline_lines(&mut self) -> Option<&mut Vec<Range<usize>>> {
+    Some(&mut self.inline.lines)
   }
   fn open(p: &mut Tokenizer, stack: &mut Vec<Box<dyn Container>>) -> bool
   where
     Self: Sized,
   {
-    p.add_container(
-      stack,
-      Para { inline_parser: inline::Tokenizer::new(p.subject.clone(), p.opts.clone()) },
-    );
+    p.add_container(stack, Para { inline: InlineJob::new(p) });
     p.add_match(p.pos..p.pos, Comp::Para.add());
     true
   }
 
   fn cont(&mut self, p: &mut Tokenizer) -> bool {
-    p.find("^%S").is_match
+    p.find(pat!("^%S")).is_match
+  }
+
+  fn close(self: Box<Self>, p: &mut Tokenizer) {
+    p.get_inline_matches(self.inline);
+    let end = p.pos.saturating_sub(1);
+    p.add_match(end..end, Comp::Para.sub())
+  }
+}
+
+struct Heading {
+  level: usize,
+  inline: InlineJob,
+}
+
+impl Container for Heading {
+  fn content(&self) -> &'static str {
+    "inline"
+  }
+  fn inline_lines(&mut self) -> Option<&mut Vec<Range<usize>>> {
+    Some(&mut self.inline.lines)
+  }
+  fn open(p: &mut Tokenizer, stack: &mut Vec<Box<dyn Container>>) -> bool
+  where
+    Self: Sized,
+  {
+    let m = p.find(pat!("^#+"));
+    if !(m.is_match && find_at(&p.subject, pat!("^%s"), m.end).is_match) {
+      return false;
+    }
+    let mut inline = InlineJob::new(p);
+    inline.close = Some((Comp::Heading, m.end));
+    p.add_container(stack, Heading { level: m.end - m.start, inline });
+    p.add_match(m.start..m.end, Comp::Heading.add());
+    p.pos = m.end;
+    true
+  }
+
+  fn cont(&mut self, p: &mut Tokenizer) -> bool {
+    let m = p.find(pat!("^#+%s"));
+    if m.is_match && m.end - m.start - 1 == self.level {
+      p.pos = m.end - 1;
+      true
+    } else {
+      false
+    }
   }
 
-  fn close(mut self: Box<Self>, p: &mut Tokenizer) {
-    p.matches.extend(self.inline_parser.get_matches());
-    p.add_match(p.pos - 1..p.pos - 1, Comp::Para.sub())
+  fn close(self: Box<Self>, p: &mut Tokenizer) {
+    p.get_inline_matches(self.inline);
   }
 }
 
@@ -91,10 +192,10 @@ impl Container for CodeBlock {
     Self: Sized,
   {
     let mut border = '`';
-    let mut m = p.find("^```([ \t]*)([^%s`]*)[ \t]*[\r\n]");
+    let mut m = p.find(pat!("^```([ \t]*)([^%s`]*)[ \t]*[\r\n]"));
     if !m.is_match {
       border = '~';
-      m = p.find("^~~~([ \t]*)([^%s`]*)[ \t]*[\r\n]");
+      m = p.find(pat!("^~~~([ \t]*)([^%s`]*)[ \t]*[\r\n]"));
     }
     if !m.is_match {
       return false;
@@ -113,8 +214,11 @@ impl Container for CodeBlock {
   }
 
   fn cont(&mut self, p: &mut Tokenizer) -> bool {
-    let m =
-      if self.border == '`' { p.find("^(```)[ \t]*[\r\n]") } else { p.find("^(~~~)[ \t]*[\r\n]") };
+    let m = if self.border == '`' {
+      p.find(pat!("^(```)[ \t]*[\r\n]"))
+    } else {
+      p.find(pat!("^(~~~)[ \t]*[\r\n]"))
+    };
     if m.is_match {
       p.pos = m.end - 1;
       p.finished_line = true;
@@ -142,7 +246,7 @@ impl Container for ReferenceDefinition {
   where
     Self: Sized,
   {
-    let m = p.find("^[[]([^\r\n]*)%]:[ \t]*(%S*)");
+    let m = p.find(pat!("^[[]([^\r\n]*)%]:[ \t]*(%S*)"));
     if !m.is_match {
       return false;
     }
@@ -160,7 +264,7 @@ impl Container for ReferenceDefinition {
     if self.indent >= p.indent {
       return false;
     }
-    let m = p.find("^(%S+)");
+    let m = p.find(pat!("^(%S+)"));
     if m.is_match {
       p.add_match(m.cap1.start..m.cap1.end, Atom::ReferenceValue);
       p.pos = m.end;
@@ -171,24 +275,76 @@ impl Container for ReferenceDefinition {
   fn close(self: Box<Self>, p: &mut Tokenizer) {
     p.add_match(p.pos..p.pos, Comp::ReferenceDefinition.sub())
   }
+}
 
-  fn inline_parser(&mut self) -> Option<&mut inline::Tokenizer> {
-    None
+/// `{#id .class}` on lines of their own, for the next block. Lines after the
+/// first one need to be indented.
+struct BlockAttributes {
+  /// End of the last line.
+  end: usize,
+}
+
+impl Container for BlockAttributes {
+  fn content(&self) -> &'static str {
+    "attributes"
+  }
+
+  fn open(p: &mut Tokenizer, stack: &mut Vec<Box<dyn Container>>) -> bool
+  where
+    Self: Sized,
+  {
+    if !p.find(pat!("^{")).is_match {
+      return false;
+    }
+    // Parse ahead: if the attributes don't end a line, this is a paragraph.
+    let mut tokenizer = attribute::Tokenizer::new(Arc::clone(&p.subject));
+    let (mut line, mut endeol) = (p.pos..p.starteol, p.endeol);
+    loop {
+      match tokenizer.feed(line.start, line.end) {
+        (attribute::Status::Done, ep) if ep + 1 == line.end => break,
+        (attribute::Status::Continue, _) => {
+          let m = find_at(&p.subject, pat!("^[ \t]*"), endeol);
+          let eol = find_at(&p.subject, pat!("[\r]?[\n]"), m.end);
+          if m.end - m.start <= p.indent || !eol.is_match || eol.end > p.input_limit() {
+            return false;
+          }
+          (line, endeol) = (m.end..eol.start, eol.end);
+        }
+        _ => return false,
+      }
+    }
+    p.add_container(stack, BlockAttributes { end: line.end });
+    p.add_match(p.pos..p.pos, Comp::BlockAttributes.add());
+    p.matches.extend(tokenizer.get_matches());
+    p.add_match(line.end..line.end, Comp::BlockAttributes.sub());
+    p.pos = p.starteol;
+    true
+  }
+
+  fn cont(&mut self, p: &mut Tokenizer) -> bool {
+    if p.pos >= self.end {
+      return false;
+    }
+    p.pos = p.starteol;
+    true
+  }
+
+  fn close(self: Box<Self>, p: &mut Tokenizer) {
+    p.attributes_pending = true;
   }
 }
 
 impl Tokenizer {
-  pub fn new(mut subject: String, opts: ParseOpts) -> Tokenizer {
-    if !find(&subject, "[\r\n]$").is_match {
-      subject.push('\n');
-    }
+  /// The subject is a copy of `text`, plus a final newline if it lacks one.
+  pub fn new(text: &str, opts: ParseOpts) -> Tokenizer {
     let mut res = Tokenizer::default();
-    res.subject = subject;
+    res.subject =
+      if find(text, pat!("[\r\n]$")).is_match { text.into() } else { format!("{text}\n").into() };
     res.opts = opts;
     res
   }
 
-  fn find(&self, pat: &'static str) -> PatMatch {
+  fn find(&self, pat: &Pattern) -> PatMatch {
     find_at(&self.subject, pat, self.pos)
   }
 
@@ -207,19 +363,118 @@ impl Tokenizer {
     {
       stack.pop().unwrap().close(self)
     }
+    self.attributes_pending = false;
     stack.push(Box::new(container))
   }
 
   fn skip_space(&mut self) {
-    let m = find_at(&self.subject, "[^ \t]", self.pos);
+    let m = find_at(&self.subject, pat!("[^ \t]"), self.pos);
     if m.is_match {
       self.indent = m.start - self.startline;
       self.pos = m.start;
     }
   }
 
+  /// Start of the first line past `max_input_bytes`.
+  fn input_limit(&self) -> usize {
+    let max = self.opts.max_input_bytes;
+    if self.subject.len() <= max {
+      return self.subject.len();
+    }
+    self.subject.as_bytes()[..max].iter().rposition(|&it| it == b'\n').map_or(0, |idx| idx + 1)
+  }
+
+  /// Keeps the input we stopped parsing at as a single literal paragraph.
+  fn literal_tail(&mut self) {
+    let end = self.subject.trim_end_matches(['\r', '\n']).len();
+    if self.pos < end {
+      self.add_match(self.pos..self.pos, Comp::Para.add());
+      self.add_match(self.pos..end, Atom::Str);
+      self.add_match(end..end, Comp::Para.sub());
+      self.pos = self.subject.len();
+    }
+  }
+
+  /// The match budget counts inline matches as they are produced, so it needs
+  /// serial parsing.
+  fn parallel(&self) -> bool {
+    self.opts.parallel && self.opts.max_matches == usize::MAX
+  }
+
+  /// Tokenizes the paragraphs deferred with [`ParseOpts::parallel`] on a few
+  /// threads, splicing their matches into the block matches.
+  fn tokenize_inlines(&mut self) {
+    let jobs = std::mem::take(&mut self.inline_jobs);
+    if jobs.is_empty() {
+      return;
+    }
+    let threads = thread::available_parallelism().map_or(1, |it| it.get());
+
+    // Runs of consecutive paragraphs, a few per thread to even out the load.
+    let total: usize = jobs.iter().flat_map(|it| &it.lines).map(|it| it.len()).sum();
+    let chunk_size = total / (4 * threads) + 1;
+    let mut chunks = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (idx, job) in jobs.iter().enumerate() {
+      size += job.lines.iter().map(|it| it.len()).sum::<usize>();
+      if size >= chunk_size || idx + 1 == jobs.len() {
+        chunks.push(&jobs[start..idx + 1]);
+        (start, size) = (idx + 1, 0);
+      }
+    }
+
+    let next = AtomicUsize::new(0);
+    let subject = &self.subject;
+    let mut results: Vec<(usize, Vec<_>)> = thread::scope(|s| {
+      let workers: Vec<_> = (0..threads.min(chunks.len()))
+        .map(|_| {
+          s.spawn(|| {
+            let mut res = Vec::new();
+            loop {
+              let idx = next.fetch_add(1, Ordering::Relaxed);
+              let Some(chunk) = chunks.get(idx) else { break };
+              res.push((idx, chunk.iter().map(|job| job.tokenize(subject)).collect()));
+            }
+            res
+          })
+        })
+        .collect();
+      workers
+        .into_iter()
+        .flat_map(|it| it.join().unwrap_or_else(|err| std::panic::resume_unwind(err)))
+        .collect()
+    });
+    results.sort_by_key(|it| it.0);
+
+    let block_matches = std::mem::take(&mut self.matches);
+    let mut block_matches = block_matches.into_iter();
+    let mut pos = 0;
+    for (job, (inline_matches, warnings)) in
+      jobs.iter().zip(results.into_iter().flat_map(|it| it.1))
+    {
+      self.matches.extend(block_matches.by_ref().take(job.idx - pos));
+      self.matches.extend(inline_matches);
+      self.warnings.extend(warnings);
+      pos = job.idx;
+    }
+    self.matches.extend(block_matches);
+  }
+
+  /// Tokenizes the inline content of a closing container, or defers it with
+  /// [`ParseOpts::parallel`].
+  fn get_inline_matches(&mut self, mut inline: InlineJob) {
+    if self.parallel() {
+      inline.idx = self.matches.len();
+      self.inline_jobs.push(inline);
+    } else {
+      let (matches, warnings) = inline.tokenize(&self.subject);
+      self.matches.extend(matches);
+      self.warnings.extend(warnings);
+    }
+  }
+
   fn get_eol(&mut self) {
-    let mut m = find_at(&self.subject, "[\r]?[\n]", self.pos);
+    let mut m = find_at(&self.subject, pat!("[\r]?[\n]"), self.pos);
     if !m.is_match {
       (m.start, m.end) = (self.subject.len(), self.subject.len());
     }
@@ -230,8 +485,22 @@ impl Tokenizer {
   pub fn parse(&mut self) {
     let mut containers: Vec<Box<dyn Container>> = Vec::new();
 
-    let subjectlen = self.subject.len();
-    while self.pos < subjectlen {
+    let subjectlen = self.input_limit();
+    if self.pos == 0 {
+      if let Some((end, metadata)) = metadata::front_matter(&self.subject[..subjectlen]) {
+        self.front_matter = end;
+        self.metadata = metadata;
+        self.pos = end;
+      }
+    }
+    while self.pos < subjectlen && self.matches.len() < self.opts.max_matches {
+      // Block attributes and their block are parsed together.
+      if containers.is_empty() && !self.attributes_pending {
+        if self.resync.binary_search(&self.pos).is_ok() {
+          return self.tokenize_inlines();
+        }
+        self.block_starts.push(self.pos);
+      }
       self.indent = 0;
       self.startline = self.pos;
       self.finished_line = false;
@@ -265,7 +534,7 @@ impl Tokenizer {
         let last_match = containers[..self.last_matched_container].first();
         let mut check_starts = !is_blank
           && !matches!(last_match, Some(c) if c.content() != "block")
-          && !self.find("^%a+%s").is_match; // optimization
+          && !self.find(pat!("^%a+%s")).is_match; // optimization
 
         while check_starts {
           check_starts = false;
@@ -278,7 +547,7 @@ impl Tokenizer {
               } else {
                 self.skip_space();
                 new_starts = true;
-                check_starts = containers.last().unwrap().content() != "text"
+                check_starts = containers.last().unwrap().content() == "block"
               }
               break;
             }
@@ -322,9 +591,9 @@ impl Tokenizer {
                 startpos = startpos - (self.indent - tip_indent)
               }
               self.add_match(startpos..self.endeol, Atom::Str)
-            } else if let Some(inline_parser) = tip.inline_parser() {
+            } else if let Some(inline_lines) = tip.inline_lines() {
               if !is_blank {
-                inline_parser.feed(self.pos, self.endeol)
+                inline_lines.push(self.pos..self.endeol)
               }
             }
           }
@@ -341,6 +610,8 @@ impl Tokenizer {
     while let Some(cont) = containers.pop() {
       cont.close(self)
     }
+    self.literal_tail();
+    self.tokenize_inlines();
     if self.opts.debug_matches {
       for m in &self.matches {
         let ms = format!(
@@ -0,0 +1,97 @@
+//! Likely mistakes in the source, see [`Document::diagnostics`].
+//!
+//! The tree builder and the tokenizers record [`Finding`]s by byte range as
+//! they go, they are kept with the document and reparsed along with it. Only
+//! turning them into diagnostics needs the document as a whole.
+use std::{
+  collections::{BTreeMap, HashSet},
+  ops::Range,
+};
+
+use crate::{
+  ast::{SourcePos, SourceSpan},
+  Diagnostic, Document, Severity,
+};
+
+/// A problem the tokenizers recover from, at a byte offset.
+#[derive(Debug, Clone)]
+pub(crate) struct Warning {
+  pub(crate) offset: usize,
+  pub(crate) message: &'static str,
+}
+
+impl Warning {
+  pub(crate) fn new(offset: usize, message: &'static str) -> Warning {
+    Warning { offset, message }
+  }
+}
+
+/// Something in the source diagnostics are made of.
+#[derive(Debug, Clone)]
+pub(crate) struct Finding {
+  pub(crate) range: Range<usize>,
+  pub(crate) kind: Kind,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Kind {
+  /// A link or image without a destination, going to this reference key.
+  Reference(String),
+  /// A reference definition with this key.
+  Definition(String),
+  UnknownEmoji(String),
+  Warning(&'static str),
+}
+
+impl From<Warning> for Finding {
+  fn from(warning: Warning) -> Finding {
+    Finding { range: warning.offset..warning.offset, kind: Kind::Warning(warning.message) }
+  }
+}
+
+pub(crate) fn check(doc: &Document) -> Vec<Diagnostic> {
+  let subject = doc.state.subject();
+  let findings = doc.state.findings();
+  let line_starts: Vec<usize> =
+    std::iter::once(0).chain(subject.match_indices('\n').map(|(idx, _)| idx + 1)).collect();
+  let source_pos = |offset: usize| {
+    let line = line_starts.partition_point(|&it| it <= offset);
+    let line_start = line_starts[line - 1];
+    let col = subject[line_start..offset].chars().count() + 1;
+    SourcePos { line, col, offset }
+  };
+
+  let used: HashSet<&str> = findings
+    .iter()
+    .filter_map(|it| match &it.kind {
+      Kind::Reference(key) => Some(key.as_str()),
+      _ => None,
+    })
+    .collect();
+  let mut seen: BTreeMap<&str, usize> = BTreeMap::new();
+  let mut res = Vec::new();
+  for finding in findings {
+    let span =
+      SourceSpan { start: source_pos(finding.range.start), end: source_pos(finding.range.end) };
+    let mut add = |severity, message| res.push(Diagnostic { span, severity, message });
+    match &finding.kind {
+      Kind::Reference(key) => {
+        if !doc.references.contains_key(key) {
+          add(Severity::Error, format!("undefined reference `{key}`"))
+        }
+      }
+      Kind::Definition(key) => {
+        if let Some(line) = seen.insert(key, span.start.line) {
+          add(Severity::Warning, format!("reference `{key}` is already defined on line {line}"));
+        }
+        if !used.contains(key.as_str()) {
+          add(Severity::Hint, format!("reference `{key}` is never used"));
+        }
+      }
+      Kind::UnknownEmoji(alias) => add(Severity::Warning, format!("unknown emoji `:{alias}:`")),
+      Kind::Warning(message) => add(Severity::Warning, message.to_string()),
+    }
+  }
+  res.sort_by_key(|it| (it.span.start.offset, it.severity));
+  res
+}
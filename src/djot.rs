@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+  ast::{normalize_label, Attrs, Tag},
+  metadata,
+  patterns::{find_at, pat},
+  text,
+  tree::get_identifier,
+  Document,
+};
+
+/// Renders the document back to djot, parsing the result gives the same tree
+/// (source positions aside).
+pub(crate) fn convert(doc: &Document) -> String {
+  let mut ctx = Ctx {
+    res: String::new(),
+    para_start: 0,
+    open: Vec::new(),
+    identifiers: HashSet::new(),
+    heading_id: None,
+  };
+  ctx.render_doc(doc);
+  ctx.res
+}
+
+/// Attributes in braces, in the order they come in.
+pub(crate) fn render_attrs(attrs: &Attrs) -> String {
+  let attrs = attrs.iter().map(|(k, v)| match k.as_str() {
+    "id" => format!("#{v}"),
+    "class" => v.split(' ').map(|it| format!(".{it}")).collect::<Vec<_>>().join(" "),
+    // Quoted values are taken as is, backslashes included.
+    _ => format!("{k}=\"{v}\""),
+  });
+  format!("{{{}}}", attrs.collect::<Vec<_>>().join(" "))
+}
+
+struct Ctx {
+  res: String,
+  /// Where the text of the paragraph being written starts: a `#` there would
+  /// start a heading.
+  para_start: usize,
+  /// Delimiters of the enclosing inlines.
+  open: Vec<&'static str>,
+  /// Section ids so far, to tell which ones the parser generates.
+  identifiers: HashSet<String>,
+  /// An id to give the next heading explicitly.
+  heading_id: Option<String>,
+}
+
+impl Ctx {
+  fn render_doc(&mut self, doc: &Document) {
+    if !doc.metadata.is_empty() {
+      self.out(&metadata::render(&doc.metadata));
+    }
+    self.render_blocks(&doc.children);
+    let mut implicit = BTreeMap::new();
+    implicit_references(&doc.children, &mut implicit);
+    for (key, reference_definition) in &doc.references {
+      // Headings define these anyway.
+      if reference_definition.attrs.is_empty()
+        && implicit.get(key.as_str()) == Some(&reference_definition.destination)
+      {
+        continue;
+      }
+      if !self.res.is_empty() {
+        self.out("\n");
+      }
+      self.render_block_attrs(&reference_definition.attrs);
+      self.out(&format!("[{key}]: {}\n", reference_definition.destination));
+    }
+  }
+
+  fn render_blocks(&mut self, blocks: &[Tag]) {
+    for block in blocks {
+      // The first block of the section is separated already.
+      if !self.res.is_empty() && !matches!(block, Tag::Section(_)) {
+        self.out("\n");
+      }
+      self.render(block)
+    }
+  }
+
+  fn render(&mut self, tag: &Tag) {
+    match tag {
+      Tag::Section(section) => {
+        if let (Some(Tag::Heading(heading)), Some(id)) =
+          (section.children.first(), section.attrs.get("id"))
+        {
+          let text = text::inline_text(&heading.children);
+          if get_identifier(&self.identifiers, text.trim()) != *id {
+            self.heading_id = Some(id.clone());
+          }
+          self.identifiers.insert(id.clone());
+        }
+        self.render_blocks(&section.children)
+      }
+      Tag::Heading(heading) => {
+        let mut attrs = heading.attrs.clone();
+        if let Some(id) = self.heading_id.take() {
+          attrs.insert("id".to_string(), id);
+        }
+        self.render_block_attrs(&attrs);
+        self.out(&"#".repeat(heading.level as usize));
+        self.out(" ");
+        let start = self.res.len();
+        self.render_children(&heading.children);
+        self.guard_whitespace(start);
+        self.out("\n");
+      }
+      Tag::Para(para) => {
+        self.render_block_attrs(&para.attrs);
+        let start = self.res.len();
+        self.para_start = start;
+        self.render_children(&para.children);
+        self.guard_whitespace(start);
+        self.out("\n");
+      }
+      Tag::CodeBlock(code_block) => {
+        self.render_block_attrs(&code_block.attrs);
+        // Only three char fences are recognized, pick one not closing early.
+        let closes = |fence| code_block.text.lines().any(|it| it.trim_start().starts_with(fence));
+        let fence = if closes("```") { "~~~" } else { "```" };
+        self.out(fence);
+        if let Some(lang) = &code_block.lang {
+          self.out(" ");
+          self.out(lang);
+        }
+        self.out("\n");
+        self.out(&code_block.text);
+        if !code_block.text.is_empty() && !code_block.text.ends_with('\n') {
+          self.out("\n");
+        }
+        self.out(fence);
+        self.out("\n");
+      }
+      Tag::Link(link) => {
+        self.out("[");
+        self.render_children(&link.children);
+        self.out("]");
+        self.render_destination(
+          &link.children,
+          link.destination.as_deref(),
+          link.reference.as_deref(),
+        );
+        self.render_attrs(&link.attrs);
+      }
+      Tag::Image(image) => {
+        self.out("![");
+        self.render_children(&image.children);
+        self.out("]");
+        self.render_destination(
+          &image.children,
+          image.destination.as_deref(),
+          image.reference.as_deref(),
+        );
+        self.render_attrs(&image.attrs);
+      }
+      Tag::Strong(strong) => self.render_delimited("*", &strong.children, "*", &strong.attrs),
+      Tag::Emph(emph) => self.render_delimited("_", &emph.children, "_", &emph.attrs),
+      Tag::Insert(insert) => self.render_delimited("{+", &insert.children, "+}", &insert.attrs),
+      Tag::Delete(delete) => self.render_delimited("{-", &delete.children, "-}", &delete.attrs),
+      Tag::Mark(mark) => self.render_delimited("{=", &mark.children, "=}", &mark.attrs),
+      Tag::Superscript(superscript) => {
+        self.render_delimited("^", &superscript.children, "^", &superscript.attrs)
+      }
+      Tag::Subscript(subscript) => {
+        self.render_delimited("~", &subscript.children, "~", &subscript.attrs)
+      }
+      Tag::Span(span) => self.render_delimited("[", &span.children, "]", &span.attrs),
+      Tag::DoubleQuoted(double_quoted) => {
+        self.render_delimited("\"", &double_quoted.children, "\"", &double_quoted.attrs)
+      }
+      Tag::Url(url) => {
+        self.out(&format!("<{}>", url.destination));
+        self.render_attrs(&url.attrs);
+      }
+      Tag::SoftBreak(_) => self.out("\n"),
+      Tag::EmDash(_) => self.out("---"),
+      Tag::EnDash(_) => self.out("--"),
+      Tag::Verbatim(verbatim) => {
+        let ticks = "`".repeat(longest_run(&verbatim.text, '`') + 1);
+        // A space next to a backtick is padding.
+        let lpad = if verbatim.text.starts_with('`') { " " } else { "" };
+        let rpad = if verbatim.text.ends_with('`') { " " } else { "" };
+        self.out(&format!("{ticks}{lpad}{}{rpad}{ticks}", verbatim.text));
+        self.render_attrs(&verbatim.attrs);
+      }
+      Tag::Str(str) => {
+        // Attributes after a word apply to that word only.
+        let word = !str.text.contains(|it: char| it.is_ascii_whitespace());
+        if str.attrs.is_empty() || word {
+          self.out_escape_djot(&str.text);
+          self.render_attrs(&str.attrs);
+        } else {
+          self.out("[");
+          self.out_escape_djot(&str.text);
+          self.out("]");
+          self.render_attrs(&str.attrs);
+        }
+      }
+      Tag::Emoji(emoji) => self.out(&format!(":{}:", emoji.alias)),
+    }
+  }
+
+  fn render_children(&mut self, children: &[Tag]) {
+    for child in children {
+      self.render(child)
+    }
+  }
+
+  fn render_delimited(
+    &mut self,
+    open: &'static str,
+    children: &[Tag],
+    close: &'static str,
+    attrs: &Attrs,
+  ) {
+    let start = self.res.len();
+    self.out(open);
+    let nested = self.open.contains(&open);
+    self.open.push(open);
+    self.render_children(children);
+    self.open.pop();
+    // `_ a_` is no emphasis, `{_ a_}` is.
+    let inner = &self.res[start + open.len()..];
+    let explicit = matches!(open, "*" | "_" | "^" | "~")
+      && (nested
+        || inner.is_empty()
+        || inner.starts_with(char::is_whitespace)
+        || inner.ends_with(char::is_whitespace));
+    if explicit {
+      self.res.insert(start, '{');
+    }
+    self.out(close);
+    if explicit {
+      self.out("}");
+    }
+    self.render_attrs(attrs);
+  }
+
+  fn render_destination(
+    &mut self,
+    children: &[Tag],
+    destination: Option<&str>,
+    reference: Option<&str>,
+  ) {
+    match (destination, reference) {
+      (Some(destination), _) => self.out(&format!("({destination})")),
+      (None, Some(reference)) if reference == text::inline_text(children) => self.out("[]"),
+      (None, Some(reference)) => self.out(&format!("[{reference}]")),
+      (None, None) => self.out("()"),
+    }
+  }
+
+  fn render_block_attrs(&mut self, attrs: &Attrs) {
+    if !attrs.is_empty() {
+      self.render_attrs(attrs);
+      self.out("\n");
+    }
+  }
+
+  fn render_attrs(&mut self, attrs: &Attrs) {
+    if !attrs.is_empty() {
+      self.out(&render_attrs(attrs));
+    }
+  }
+
+  /// The parser trims whitespace around the text of a block, empty attributes
+  /// keep it.
+  fn guard_whitespace(&mut self, start: usize) {
+    if self.res[start..].starts_with(char::is_whitespace) {
+      self.res.insert_str(start, "{}");
+    }
+    if self.res[start..].ends_with(char::is_whitespace) {
+      self.out("{}");
+    }
+  }
+
+  fn out(&mut self, s: &str) {
+    self.res.push_str(s)
+  }
+
+  /// Escapes what would otherwise start markup.
+  fn out_escape_djot(&mut self, s: &str) {
+    for (idx, c) in s.char_indices() {
+      let escape = match c {
+        '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '<' | '~' | '^' | '"' => true,
+        '-' => s[idx + 1..].starts_with('-'),
+        '#' => self.res.len() == self.para_start,
+        ':' => find_at(s, pat!("^:[%w_+-]+:"), idx).is_match,
+        _ => false,
+      };
+      if escape {
+        self.res.push('\\');
+      }
+      self.res.push(c);
+    }
+  }
+}
+
+/// What each heading defines, see [`crate::tree::sections`].
+fn implicit_references(children: &[Tag], acc: &mut BTreeMap<String, String>) {
+  for child in children {
+    if let Tag::Section(section) = child {
+      if let (Some(Tag::Heading(heading)), Some(id)) =
+        (section.children.first(), section.attrs.get("id"))
+      {
+        let key = normalize_label(&text::inline_text(&heading.children));
+        acc.entry(key).or_insert_with(|| format!("#{id}"));
+      }
+      implicit_references(&section.children, acc);
+    }
+  }
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+  text.split(|it| it != c).map(str::len).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::Document;
+
+  #[test]
+  fn to_djot() {
+    let doc = Document::parse("_a_ \"b\" `` `c ``{#d} [e][]\n\n[e]: /f\n");
+    assert_eq!(doc.to_djot(), "_a_ \"b\" `` `c ``{#d} [e][]\n\n[e]: /f\n");
+    assert_eq!(doc.to_text(), "a “b” `c  e\n");
+
+    // Attribute values keep their backslashes, empty attributes keep the
+    // whitespace the parser would trim.
+    for (source, want) in [
+      ("hi{key=\"\\{#hi\"}\n", "hi{key=\"\\{#hi\"}\n"),
+      ("{#id} at beginning\n", "{} at beginning\n"),
+      ("After {#id} space\n{.class}\n", "After {#id} space\n{}\n"),
+    ] {
+      let doc = Document::parse(source);
+      assert_eq!(doc.to_djot(), want);
+      assert_eq!(Document::parse(want).to_html(), doc.to_html());
+    }
+  }
+}
@@ -1,3 +1,4 @@
+//! The emoji [`crate::ast::Emoji`] aliases stand for, like `:+1:`.
 pub(crate) fn find_emoji(s: &str) -> Option<&'static str> {
   let idx = EMOJI_LIST.binary_search_by_key(&s, |&(k, _)| k).ok()?;
   Some(EMOJI_LIST[idx].1)
@@ -17,7 +18,8 @@ fn emoji_list_is_sorted() {
   }
 }
 
-static EMOJI_LIST: &[(&str, &str)] = &[
+/// `(alias, emoji)` pairs, sorted by alias.
+pub static EMOJI_LIST: &[(&str, &str)] = &[
   ("+1", "👍"),
   ("-1", "👎"),
   ("100", "💯"),
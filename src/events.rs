@@ -0,0 +1,181 @@
+//! Pull parser: a flat stream of events instead of a `Document` tree.
+//!
+//! The events mirror the `+para`/`-para` annotations of the internal match
+//! stream, with attributes already attached to the element they decorate.
+use std::collections::VecDeque;
+
+use crate::{
+  annot::Annot,
+  ast::{borrowed::attrs_into_owned, Attrs},
+  attribute::collect_attrs,
+  block, Match, ParseOpts,
+};
+
+pub use crate::annot::{Atom, Comp as Container};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+  Start(Container, Attrs),
+  End(Container),
+  Text(&'a str),
+  Atom(Atom, &'a str),
+}
+
+pub struct Events<'a> {
+  text: &'a str,
+  matches: Vec<Match>,
+  idx: usize,
+  pending: VecDeque<Event<'a>>,
+  /// For each `Add` match, the index of the `Sub` closing it.
+  closers: Vec<usize>,
+  /// Matches of the block attributes for the next block.
+  block_attrs: Vec<Match>,
+}
+
+impl<'a> Events<'a> {
+  pub fn parse(text: &'a str) -> Events<'a> {
+    Events::parse_opts(ParseOpts::default(), text)
+  }
+
+  pub fn parse_opts(opts: ParseOpts, text: &'a str) -> Events<'a> {
+    let mut p = block::Tokenizer::new(text, opts);
+    p.parse();
+    let closers = find_closers(&p.matches);
+    Events {
+      text,
+      matches: p.matches,
+      idx: 0,
+      pending: VecDeque::new(),
+      closers,
+      block_attrs: Vec::new(),
+    }
+  }
+
+  fn step(&mut self) {
+    let m = self.matches[self.idx].clone();
+    self.idx += 1;
+    match m.a {
+      Annot::Add(Container::Attributes) => {
+        // Attributes which weren't claimed by a preceding element.
+        self.skip_attrs();
+      }
+      Annot::Add(Container::BlockAttributes) => {
+        let len = self.matches[self.idx..]
+          .iter()
+          .position(|it| it.is(Container::BlockAttributes.sub()))
+          .unwrap_or(self.matches.len() - self.idx);
+        self.block_attrs.extend_from_slice(&self.matches[self.idx..self.idx + len]);
+        self.idx = (self.idx + len + 1).min(self.matches.len());
+      }
+      Annot::Add(comp) => {
+        // Block attributes go to the block after them.
+        let block_attrs = std::mem::take(&mut self.block_attrs);
+        let mut attrs = attrs_into_owned(collect_attrs(self.text, &block_attrs));
+        attrs.extend(self.trailing_attrs(self.idx - 1));
+        self.pending.push_back(Event::Start(comp, attrs))
+      }
+      Annot::Sub(comp) => {
+        self.pending.push_back(Event::End(comp));
+        self.skip_attrs();
+      }
+      Annot::Atom(Atom::Str) => {
+        let attrs = self.get_attrs(self.idx);
+        if attrs.is_empty() {
+          self.push_text(m.range.start, m.range.end);
+          return;
+        }
+        // Like the tree, attributes on a bare word apply to the last word only.
+        let text = self.slice(m.range.start, m.range.end);
+        let split = text.rfind(|it: char| it.is_ascii_whitespace()).map_or(0, |idx| idx + 1);
+        self.push_text(m.range.start, m.range.start + split);
+        self.pending.push_back(Event::Start(Container::Span, attrs));
+        self.push_text(m.range.start + split, m.range.end);
+        self.pending.push_back(Event::End(Container::Span));
+        self.skip_attrs();
+      }
+      Annot::Atom(atom) => {
+        let text = self.slice(m.range.start, m.range.end);
+        self.pending.push_back(Event::Atom(atom, text))
+      }
+    }
+  }
+
+  /// For `[span]{.attrs}`, the attributes follow the closing match.
+  fn trailing_attrs(&self, add: usize) -> Attrs {
+    match self.closers[add] {
+      usize::MAX => Attrs::new(),
+      sub => self.get_attrs(sub + 1),
+    }
+  }
+
+  fn get_attrs(&self, idx: usize) -> Attrs {
+    match self.attrs_end(idx) {
+      Some(end) => attrs_into_owned(collect_attrs(self.text, &self.matches[idx + 1..end])),
+      None => Attrs::new(),
+    }
+  }
+
+  fn skip_attrs(&mut self) {
+    if let Some(end) = self.attrs_end(self.idx) {
+      self.idx = end + 1;
+    }
+  }
+
+  fn attrs_end(&self, idx: usize) -> Option<usize> {
+    if !self.matches.get(idx)?.is(Container::Attributes.add()) {
+      return None;
+    }
+    let len = self.matches[idx..].iter().position(|it| it.is(Container::Attributes.sub()))?;
+    Some(idx + len)
+  }
+
+  fn push_text(&mut self, start: usize, end: usize) {
+    if start == end {
+      return;
+    }
+    let text = self.slice(start, end);
+    if !text.is_empty() {
+      self.pending.push_back(Event::Text(text));
+    }
+    // The tokenizer terminates the last line if the input doesn't.
+    if end > self.text.len() {
+      self.pending.push_back(Event::Text("\n"));
+    }
+  }
+
+  fn slice(&self, start: usize, end: usize) -> &'a str {
+    let len = self.text.len();
+    &self.text[start.min(len)..end.min(len)]
+  }
+}
+
+/// Pairs up `Add` and `Sub` matches in one pass; unclosed ones map to
+/// `usize::MAX`.
+fn find_closers(matches: &[Match]) -> Vec<usize> {
+  let mut res = vec![usize::MAX; matches.len()];
+  let mut open: Vec<(Container, usize)> = Vec::new();
+  for (idx, m) in matches.iter().enumerate() {
+    match m.a {
+      Annot::Add(comp) => open.push((comp, idx)),
+      Annot::Sub(comp) => {
+        if let Some(pos) = open.iter().rposition(|&(it, _)| it == comp) {
+          res[open[pos].1] = idx;
+          open.remove(pos);
+        }
+      }
+      Annot::Atom(_) => (),
+    }
+  }
+  res
+}
+
+impl<'a> Iterator for Events<'a> {
+  type Item = Event<'a>;
+
+  fn next(&mut self) -> Option<Event<'a>> {
+    while self.pending.is_empty() && self.idx < self.matches.len() {
+      self.step()
+    }
+    self.pending.pop_front()
+  }
+}
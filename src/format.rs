@@ -0,0 +1,480 @@
+//! Formatting djot source, see [`crate::format`].
+//!
+//! The text of each top-level block is kept as written, except for the
+//! attributes, the code fences and, with a width, the line breaks within
+//! paragraphs. The blocks come from the match stream of the block tokenizer,
+//! so the parser decides where a block starts and ends.
+use std::ops::Range;
+
+use crate::{
+  annot::{Annot, Atom, Comp},
+  ast::{borrowed::attrs_into_owned, Attrs, Metadata, Str, Tag, VisitorMut},
+  attribute::collect_attrs,
+  block, djot, format_to, Document, FormatOpts, Match, ParseOpts,
+};
+
+/// Inlines whose markers may have braces, as in `{_emph_}`.
+const OPTIONAL_BRACES: [Comp; 4] = [Comp::Emph, Comp::Strong, Comp::Superscript, Comp::Subscript];
+
+/// Inlines a line can't break within.
+const UNBREAKABLE: [Comp; 5] =
+  [Comp::Verbatim, Comp::Url, Comp::Email, Comp::Destination, Comp::Reference];
+
+pub(crate) fn format(text: &str, opts: &FormatOpts) -> String {
+  let mut p = block::Tokenizer::new(text, ParseOpts::default());
+  p.parse();
+  let fmt = Fmt { subject: &p.subject, matches: &p.matches, width: opts.width };
+  let res = fmt.write(&p.subject[..p.front_matter]);
+
+  // Sorting the attributes is what formatting does to them, and reflowing
+  // swaps soft breaks and spaces. Nothing else may change.
+  let spaces = opts.width.is_some();
+  debug_assert!(render(&res, spaces) == render(text, spaces), "formatting changed {text:?}");
+  res
+}
+
+/// The HTML and metadata of `text`, see [`Normalize`].
+fn render(text: &str, spaces: bool) -> (String, Metadata) {
+  let mut doc = Document::parse(text);
+  Normalize { spaces }.doc(&mut doc);
+  (doc.to_html(), doc.metadata)
+}
+
+/// A top-level block: its lines, and its matches from `Add` to `Sub`.
+struct Block {
+  comp: Comp,
+  lines: Range<usize>,
+  matches: Range<usize>,
+}
+
+struct Fmt<'a> {
+  subject: &'a str,
+  matches: &'a [Match],
+  width: Option<usize>,
+}
+
+impl Fmt<'_> {
+  fn write(&self, front_matter: &str) -> String {
+    let mut main = Vec::new();
+    let mut definitions = Vec::new();
+    if !front_matter.is_empty() {
+      main.push(front_matter.to_string());
+    }
+    let mut pending = Vec::new();
+    for block in self.blocks() {
+      if block.comp == Comp::BlockAttributes {
+        pending.push(block);
+        continue;
+      }
+      let mut res = self.block_attrs(&pending);
+      pending.clear();
+      match block.comp {
+        Comp::Para | Comp::Heading => res.push_str(&self.text_block(&block)),
+        Comp::CodeBlock => res.push_str(&self.code_block(&block)),
+        _ => res.push_str(&self.subject[block.lines.clone()]),
+      }
+      if block.comp == Comp::ReferenceDefinition {
+        definitions.push(res)
+      } else {
+        main.push(res)
+      }
+    }
+    main.extend(definitions);
+    // Attributes with no block to go to stay last, where they still go to
+    // none.
+    let attrs = self.block_attrs(&pending);
+    if !attrs.is_empty() {
+      main.push(attrs);
+    }
+    let res = main.join("\n");
+    // Text starting with `---` may be taken for front matter.
+    if front_matter.is_empty() && res.starts_with("---") {
+      return format!("\n{res}");
+    }
+    res
+  }
+
+  fn blocks(&self) -> Vec<Block> {
+    let mut res = Vec::new();
+    let mut idx = 0;
+    while idx < self.matches.len() {
+      let Annot::Add(comp) = self.matches[idx].a else {
+        idx += 1;
+        continue;
+      };
+      let Some(end) = (idx + 1..self.matches.len()).find(|&it| self.matches[it].is(comp.sub()))
+      else {
+        break;
+      };
+      let start = self.line_start(self.matches[idx].range.start);
+      let last = self.matches[idx..=end].iter().map(|it| it.range.end).max().unwrap_or_default();
+      let lines = start..self.line_end(last);
+      res.push(Block { comp, lines, matches: idx..end + 1 });
+      idx = end + 1;
+    }
+    // A block may end after the indentation of the line the next one starts
+    // on.
+    for idx in 1..res.len() {
+      res[idx - 1].lines.end = res[idx - 1].lines.end.min(res[idx].lines.start);
+    }
+    res
+  }
+
+  /// The attributes before a block on one line, in canonical order.
+  fn block_attrs(&self, blocks: &[Block]) -> String {
+    let as_written = || blocks.iter().map(|it| &self.subject[it.lines.clone()]).collect();
+    if blocks.iter().any(|it| !self.is_canonical(&it.matches)) {
+      return as_written();
+    }
+    let matches: Vec<Match> = blocks
+      .iter()
+      .flat_map(|it| &self.matches[it.matches.start + 1..it.matches.end - 1])
+      .cloned()
+      .collect();
+    match self.canonical_attrs(&matches) {
+      Some(attrs) if attrs == "{}" => String::new(),
+      Some(attrs) => format!("{attrs}\n"),
+      None => as_written(),
+    }
+  }
+
+  /// Attributes from `matches` in canonical order, unless they would start
+  /// like an inline, as in `{_k="v"}`.
+  fn canonical_attrs(&self, matches: &[Match]) -> Option<String> {
+    let mut attrs = attrs_into_owned(collect_attrs(self.subject, matches));
+    if attrs.is_empty() {
+      return Some("{}".to_string());
+    }
+    sort_attrs(&mut attrs);
+    let res = djot::render_attrs(&attrs);
+    res[1..].starts_with(|it: char| it.is_alphanumeric() || it == '#' || it == '.').then_some(res)
+  }
+
+  /// Whether the attributes from `Add` to `Sub` at `matches` can be written
+  /// in canonical order: comments, values over several lines and empty names,
+  /// as in `{ . }`, are kept as written.
+  fn is_canonical(&self, matches: &Range<usize>) -> bool {
+    let matches = &self.matches[matches.clone()];
+    let name = |it: &Match| it.is(Atom::Id) || it.is(Atom::Class) || it.is(Atom::Key);
+    if matches.iter().any(|it| name(it) && it.range.is_empty()) {
+      return false;
+    }
+    let source = matches[0].range.start..matches[matches.len() - 1].range.end;
+    let mut pos = source.start;
+    let mut outside = String::new();
+    for m in matches.iter().filter(|it| it.is(Atom::Value)) {
+      outside.push_str(&self.subject[pos..m.range.start]);
+      pos = m.range.end;
+    }
+    outside.push_str(&self.subject[pos..source.end]);
+    !outside.contains('%') && !self.subject[source].contains('\n')
+  }
+
+  /// A paragraph or heading, without the braces of emphasis markers and,
+  /// for a paragraph with a width, reflowed, as long as it renders the same.
+  fn text_block(&self, block: &Block) -> String {
+    let mut braces = true;
+    let mut res = self.inlines(block, None, braces);
+    let plain = self.inlines(block, None, false);
+    if plain != res && same_text(&res, &plain) {
+      (res, braces) = (plain, false);
+    }
+    if let (Comp::Para, Some(width)) = (block.comp, self.width) {
+      let reflowed = self.inlines(block, Some(width), braces);
+      if same_text(&res, &reflowed) {
+        return reflowed;
+      }
+    }
+    res
+  }
+
+  /// The text of a paragraph or heading, with canonical inline attributes,
+  /// with braces around markers only if `braces` and with lines filled up to
+  /// `width` if there is one.
+  fn inlines(&self, block: &Block, width: Option<usize>, braces: bool) -> String {
+    let mut end = block.lines.end;
+    while self.subject[..end].ends_with(['\n', '\r']) {
+      end -= 1;
+    }
+    // Text between the places where a line may break.
+    let mut words = vec![String::new()];
+    let mut pos = block.lines.start;
+    let mut unbreakable = 0;
+    // Closers of the markers which lost their braces.
+    let mut unbraced = Vec::new();
+    let mut idx = block.matches.start;
+    while idx < block.matches.end {
+      let m = &self.matches[idx];
+      match m.a {
+        Annot::Add(Comp::Attributes) => {
+          let (next, range, attrs) = self.inline_attrs(idx);
+          let word = words.last_mut().unwrap();
+          word.push_str(&self.subject[pos..range.start]);
+          word.push_str(&attrs);
+          pos = range.end;
+          idx = next;
+          continue;
+        }
+        Annot::Add(comp) | Annot::Sub(comp)
+          if !braces && OPTIONAL_BRACES.contains(&comp) && m.range.len() == 2 =>
+        {
+          let closer = if m.a == comp.add() { self.needless_braces(idx) } else { None };
+          if closer.is_some() || unbraced.contains(&idx) {
+            unbraced.extend(closer);
+            let word = words.last_mut().unwrap();
+            word.push_str(&self.subject[pos..m.range.start]);
+            word.push_str(self.subject[m.range.clone()].trim_matches(['{', '}']));
+            pos = m.range.end;
+          }
+        }
+        Annot::Add(comp) if UNBREAKABLE.contains(&comp) => unbreakable += 1,
+        Annot::Sub(comp) if UNBREAKABLE.contains(&comp) => unbreakable -= 1,
+        // Attributes after a space go to an empty span, after a line break
+        // to nothing.
+        Annot::Atom(Atom::Softbreak)
+          if width.is_some()
+            && unbreakable == 0
+            && m.range.end < end
+            && !self.attrs_at(idx + 1) =>
+        {
+          words.last_mut().unwrap().push_str(&self.subject[pos..m.range.start]);
+          words.push(String::new());
+          // The indentation of the next line, unless some text starts there.
+          let next = self.matches.get(idx + 1).map_or(end, |it| it.range.start);
+          pos = m.range.end;
+          while pos < next && self.subject[pos..].starts_with([' ', '\t']) {
+            pos += 1;
+          }
+        }
+        Annot::Atom(Atom::Str) if width.is_some() && unbreakable == 0 => {
+          for (offset, _) in self.subject[m.range.clone()].match_indices(' ') {
+            let space = m.range.start + offset;
+            let single = |it: Option<char>| it.is_some_and(|it| !it.is_whitespace());
+            if single(self.subject[..space].chars().next_back())
+              && single(self.subject[space + 1..].chars().next())
+              && !(space + 1 == m.range.end && self.attrs_at(idx + 1))
+            {
+              words.last_mut().unwrap().push_str(&self.subject[pos..space]);
+              words.push(String::new());
+              pos = space + 1;
+            }
+          }
+        }
+        _ => (),
+      }
+      idx += 1;
+    }
+    words.last_mut().unwrap().push_str(&self.subject[pos..end]);
+
+    let mut res = String::new();
+    let mut column = 0;
+    for (idx, word) in words.iter().enumerate() {
+      if idx > 0 {
+        let first_line = word.split('\n').next().unwrap_or_default();
+        if column + 1 + first_line.chars().count() > width.unwrap_or(usize::MAX) {
+          res.push('\n');
+          column = 0;
+        } else {
+          res.push(' ');
+          column += 1;
+        }
+      }
+      res.push_str(word);
+      column = match word.rfind('\n') {
+        Some(nl) => word[nl + 1..].chars().count(),
+        None => column + word.chars().count(),
+      };
+    }
+    res.push('\n');
+    res
+  }
+
+  /// The closer of the braced marker at `idx`, if neither needs its brace:
+  /// the content neither starts nor ends with whitespace.
+  fn needless_braces(&self, idx: usize) -> Option<usize> {
+    let open = &self.matches[idx];
+    let Annot::Add(comp) = open.a else { return None };
+    let mut depth = 0;
+    let closer = (idx..self.matches.len()).find(|&it| {
+      let m = &self.matches[it];
+      depth += (m.a == comp.add()) as i32 - (m.a == comp.sub()) as i32;
+      depth == 0
+    })?;
+    let close = &self.matches[closer];
+    let content = &self.subject[open.range.end..close.range.start];
+    let needed = content.starts_with(char::is_whitespace) || content.ends_with(char::is_whitespace);
+    (close.range.len() == 2 && !needed).then_some(closer)
+  }
+
+  fn attrs_at(&self, idx: usize) -> bool {
+    self.matches.get(idx).is_some_and(|it| it.is(Comp::Attributes.add()))
+  }
+
+  /// Adjacent attributes starting at `idx` add up to the ones in canonical
+  /// order. Gives the index after them, their range and their text.
+  fn inline_attrs(&self, idx: usize) -> (usize, Range<usize>, String) {
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    let mut next = idx;
+    while next < self.matches.len() && self.matches[next].is(Comp::Attributes.add()) {
+      let start = self.matches[next].range.start;
+      if groups.last().is_some_and(|&(_, prev)| self.matches[prev].range.end != start) {
+        break;
+      }
+      let Some(end) =
+        (next..self.matches.len()).find(|&it| self.matches[it].is(Comp::Attributes.sub()))
+      else {
+        break;
+      };
+      groups.push((next, end));
+      next = end + 1;
+    }
+    let range = self.matches[idx].range.start..self.matches[next - 1].range.end;
+    if groups.iter().any(|&(start, end)| !self.is_canonical(&(start..end + 1))) {
+      return (next, range.clone(), self.subject[range].to_string());
+    }
+    let matches: Vec<Match> =
+      groups.iter().flat_map(|&(start, end)| &self.matches[start + 1..end]).cloned().collect();
+    let res =
+      self.canonical_attrs(&matches).unwrap_or_else(|| self.subject[range.clone()].to_string());
+    (next, range, res)
+  }
+
+  /// A code block between fences with no indentation is fenced with
+  /// backticks, unless a line of the code would close them. One without a
+  /// closing fence gets one, as it no longer ends the document.
+  fn code_block(&self, block: &Block) -> String {
+    let open = &self.matches[block.matches.start];
+    let close = &self.matches[block.matches.end - 1];
+    let opening_line = self.line_end(open.range.end);
+    let closing_line = self.line_start(close.range.start);
+    let closed = closing_line >= opening_line
+      && self.subject[closing_line..].lines().next().map(str::trim)
+        == Some(&self.subject[open.range.clone()]);
+    let indent = &self.subject[block.lines.start..open.range.start];
+    let as_written = || {
+      let mut res = self.subject[block.lines.clone()].to_string();
+      if !closed {
+        format_to!(res, "{indent}{}\n", &self.subject[open.range.clone()]);
+      }
+      res
+    };
+    let code_end = if closed { closing_line } else { block.lines.end };
+    if !indent.is_empty() || opening_line > code_end {
+      return as_written();
+    }
+    let code = &self.subject[opening_line..code_end];
+    let Some(fence) = ["```", "~~~"]
+      .into_iter()
+      .find(|fence| !code.lines().any(|it| it.trim_start().starts_with(fence)))
+    else {
+      return as_written();
+    };
+    let mut res = fence.to_string();
+    let lang = self.matches[block.matches.clone()].iter().find(|it| it.is(Atom::CodeLanguage));
+    if let Some(lang) = lang {
+      format_to!(res, " {}", &self.subject[lang.range.clone()]);
+    }
+    format_to!(res, "\n{code}{fence}\n");
+    res
+  }
+
+  fn line_start(&self, pos: usize) -> usize {
+    self.subject.as_bytes()[..pos].iter().rposition(|&it| it == b'\n').map_or(0, |it| it + 1)
+  }
+
+  fn line_end(&self, pos: usize) -> usize {
+    let bytes = self.subject.as_bytes();
+    if pos > 0 && bytes[pos - 1] == b'\n' {
+      return pos;
+    }
+    bytes[pos..].iter().position(|&it| it == b'\n').map_or(bytes.len(), |it| pos + it + 1)
+  }
+}
+
+/// Whether the paragraph renders the same after reflowing. Breaking or
+/// joining lines may start another block with the first line, and a few
+/// inlines tell a line break from a space.
+fn same_text(para: &str, reflowed: &str) -> bool {
+  render(para, true) == render(reflowed, true)
+}
+
+/// Puts the attributes in the order [`Fmt`] writes them. With `spaces`, also
+/// makes soft breaks spaces, which reflowing moves.
+struct Normalize {
+  spaces: bool,
+}
+
+impl Normalize {
+  fn doc(&mut self, doc: &mut Document) {
+    self.visit_children_mut(&mut doc.children);
+    for reference_definition in doc.references.values_mut() {
+      sort_attrs(&mut reference_definition.attrs)
+    }
+  }
+}
+
+impl VisitorMut for Normalize {
+  fn visit_children_mut(&mut self, children: &mut [Tag]) {
+    for child in children {
+      if self.spaces && matches!(child, Tag::SoftBreak(_)) {
+        *child = Tag::Str(Str { text: " ".to_string(), ..Str::default() });
+      }
+      sort_attrs(child.attrs_mut());
+      self.visit_tag_mut(child)
+    }
+  }
+}
+
+/// Id, classes, then the rest by key.
+fn sort_attrs(attrs: &mut Attrs) {
+  let rank = |k: &str| match k {
+    "id" => 0,
+    "class" => 1,
+    _ => 2,
+  };
+  attrs.sort_by(|k1, _, k2, _| rank(k1).cmp(&rank(k2)).then(k1.cmp(k2)));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reflow() {
+    let opts = |width| FormatOpts { width };
+    let src = "[a]: /a\n\n{.y k=v #x}\n\n{.z}\nSome *emph text* and\n  a [link][a] with `two  spaces` and more\n\n~~~\nx\n~~~\n";
+    assert_eq!(
+      format(src, &opts(Some(20))),
+      "{#x .y .z k=\"v\"}\nSome *emph text* and\na [link][a] with\n`two  spaces` and\nmore\n\n```\nx\n```\n\n[a]: /a\n"
+    );
+    assert_eq!(
+      format(src, &opts(None)),
+      "{#x .y .z k=\"v\"}\nSome *emph text* and\n  a [link][a] with `two  spaces` and more\n\n```\nx\n```\n\n[a]: /a\n"
+    );
+    // Runs of spaces don't break, the text is kept as written.
+    let src = "a  b c\nIssue \\#1 is\n#2\n";
+    let formatted = format(src, &opts(Some(5)));
+    assert_eq!(formatted, "a  b\nc\nIssue\n\\#1\nis #2\n");
+    assert_eq!(format(&formatted, &opts(Some(5))), formatted);
+    assert_eq!(format(src, &opts(Some(80))), "a  b c Issue \\#1 is #2\n");
+
+    // Attributes with comments are kept as written.
+    let src = "{.b #a}\n{% c %}\np{#d % e %}\n";
+    assert_eq!(format(src, &opts(Some(20))), src);
+
+    // Adjacent attributes add up, a code block which a line of backticks
+    // would close keeps its tildes.
+    assert_eq!(
+      format("a{.b}{#c k=\"\\\"\"} d{}\n\n~~~ rust\n```\n~~~\n", &opts(None)),
+      "a{#c .b k=\"\\\"\"} d{}\n\n~~~ rust\n```\n~~~\n"
+    );
+  }
+
+  #[test]
+  fn emphasis_braces() {
+    let opts = |width| FormatOpts { width };
+    // Braces around emphasis markers go where they aren't needed.
+    assert_eq!(format("{_a_} {*b*}c x{^2^} {_ d _}\n", &opts(None)), "_a_ *b*c x^2^ {_ d _}\n");
+    assert_eq!(format("{_a_}{=b=}\n", &opts(None)), "_a_{=b=}\n");
+  }
+}
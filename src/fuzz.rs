@@ -0,0 +1,104 @@
+//! Entry points for the `cargo fuzz` targets in `./fuzz`.
+//!
+//! Besides not panicking, each of these checks some structural invariants and
+//! panics if they are violated.
+use crate::{annot::Annot, attribute, block, Document, Match, ParseOpts};
+
+pub fn parse(text: &str) {
+  let mut p = block::Tokenizer::new(text, ParseOpts::default());
+  p.parse();
+  check_matches(&p.subject, &p.matches);
+  Document::parse(text);
+}
+
+pub fn to_html(text: &str) {
+  let html = Document::parse(text).to_html();
+  check_html(&html);
+}
+
+pub fn to_json(text: &str) {
+  let opts = ParseOpts { source_positions: true, ..ParseOpts::default() };
+  let json = Document::parse_opts(opts, text).to_json();
+  let doc = Document::from_json(&json).unwrap();
+  assert_eq!(doc.to_json(), json);
+}
+
+/// The text before the first `|` is edited: what follows it replaces a range
+/// picked from its length. Reparsing must agree with parsing from scratch.
+pub fn reparse(text: &str) {
+  let Some((old, new)) = text.split_once('|') else { return };
+  let opts = ParseOpts { source_positions: true, ..ParseOpts::default() };
+  let mut doc = Document::parse_opts(opts.clone(), old);
+  let boundaries: Vec<usize> = (0..=old.len()).filter(|&it| old.is_char_boundary(it)).collect();
+  let start = boundaries[new.len() % boundaries.len()];
+  let end = boundaries[(new.len() * 7 + 3) % boundaries.len()].max(start);
+  doc.reparse(start..end, new);
+  let mut text = old.to_string();
+  text.replace_range(start..end, new);
+  assert_eq!(doc.to_json(), Document::parse_opts(opts, &text).to_json());
+}
+
+pub fn attributes(text: &str) {
+  if text.is_empty() {
+    return;
+  }
+  let mut tokenizer = attribute::Tokenizer::new(text.into());
+  tokenizer.feed(0, text.len() - 1);
+  let matches = tokenizer.get_matches();
+  check_ranges(text, &matches);
+}
+
+fn check_matches(subject: &str, matches: &[Match]) {
+  check_ranges(subject, matches);
+  let mut stack = Vec::new();
+  for m in matches {
+    match m.a {
+      Annot::Add(comp) => stack.push(comp),
+      Annot::Sub(comp) => {
+        assert_eq!(stack.pop(), Some(comp), "unmatched -{comp} at {}", m.range.start)
+      }
+      Annot::Atom(_) => (),
+    }
+  }
+  assert!(stack.is_empty(), "unclosed {stack:?}");
+}
+
+fn check_ranges(subject: &str, matches: &[Match]) {
+  for m in matches {
+    assert!(
+      m.range.start <= m.range.end && subject.get(m.range.clone()).is_some(),
+      "bad range {:?} for {}",
+      m.range,
+      m.a
+    );
+  }
+}
+
+/// Tags are balanced and `<` and `&` only start tags and entities.
+fn check_html(html: &str) {
+  const VOID: &[&str] = &["img", "br", "hr"];
+  let mut stack = Vec::new();
+  let mut rest = html;
+  while let Some(idx) = rest.find(['<', '&']) {
+    rest = &rest[idx..];
+    if rest.starts_with('&') {
+      let end = rest.find(';').expect("unterminated entity");
+      assert!(rest[1..end].bytes().all(|it| it.is_ascii_alphanumeric() || it == b'#'));
+      rest = &rest[end + 1..];
+      continue;
+    }
+    let end = rest.find('>').expect("unterminated tag");
+    let tag = &rest[1..end];
+    rest = &rest[end + 1..];
+    if let Some(name) = tag.strip_prefix('/') {
+      assert_eq!(stack.pop(), Some(name), "unmatched </{name}>");
+    } else {
+      let name = tag.split(' ').next().unwrap();
+      assert!(!name.is_empty() && name.bytes().all(|it| it.is_ascii_alphanumeric()), "<{tag}>");
+      if !VOID.contains(&name) {
+        stack.push(name);
+      }
+    }
+  }
+  assert!(stack.is_empty(), "unclosed {stack:?}");
+}
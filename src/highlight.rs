@@ -0,0 +1,26 @@
+//! Syntax highlighting of code blocks, see [`HtmlOpts::highlighter`].
+//!
+//! With the `highlight` feature, [`Builtin`] highlights a few common languages.
+//! It marks tokens up with the `tok-*` classes of CodeMirror's
+//! `classHighlighter`, like `<span class="tok-keyword">`, so stylesheets
+//! written for those apply.
+#[cfg(feature = "highlight")]
+mod builtin;
+
+#[cfg(doc)]
+use crate::HtmlOpts;
+
+#[cfg(feature = "highlight")]
+pub use self::builtin::Builtin;
+
+pub trait Highlighter {
+  /// The HTML inside `<code>` for `code` written in `lang`, or `None` to
+  /// escape the code as usual.
+  fn highlight(&self, lang: &str, code: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str, &str) -> Option<String>> Highlighter for F {
+  fn highlight(&self, lang: &str, code: &str) -> Option<String> {
+    self(lang, code)
+  }
+}
@@ -0,0 +1,502 @@
+//! Small lexers for Rust, Python, JSON, shell and TOML, good enough to color
+//! code but not to validate it. Djot is highlighted with the djot parser.
+use std::ops::Range;
+
+use crate::{
+  annot::{Annot, Atom, Comp},
+  block, format_to,
+  highlight::Highlighter,
+  html::escape_html,
+  ParseOpts,
+};
+
+/// Highlights `rust`, `python`, `json`, `sh`, `toml` and `djot`. Languages are
+/// matched ignoring case, and common aliases like `rs`, `py` or `bash` work
+/// too.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Builtin;
+
+impl Highlighter for Builtin {
+  fn highlight(&self, lang: &str, code: &str) -> Option<String> {
+    let lex: fn(&mut Lexer) = match lang.to_ascii_lowercase().as_str() {
+      "rust" | "rs" => rust,
+      "python" | "py" | "python3" => python,
+      "json" => json,
+      "sh" | "shell" | "bash" | "zsh" => shell,
+      "toml" => toml,
+      "djot" | "dj" => return Some(render(code, &djot(code))),
+      _ => return None,
+    };
+    let mut lx = Lexer { code, pos: 0, tokens: Vec::new() };
+    lex(&mut lx);
+    Some(render(code, &lx.tokens))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tok {
+  Keyword,
+  Atom,
+  Bool,
+  String,
+  String2,
+  Number,
+  Comment,
+  TypeName,
+  MacroName,
+  PropertyName,
+  VariableName,
+  LabelName,
+  Meta,
+  Heading,
+  Emphasis,
+  Strong,
+  Link,
+  Url,
+}
+
+impl Tok {
+  fn class(self) -> &'static str {
+    match self {
+      Tok::Keyword => "tok-keyword",
+      Tok::Atom => "tok-atom",
+      Tok::Bool => "tok-bool",
+      Tok::String => "tok-string",
+      Tok::String2 => "tok-string2",
+      Tok::Number => "tok-number",
+      Tok::Comment => "tok-comment",
+      Tok::TypeName => "tok-typeName",
+      Tok::MacroName => "tok-macroName",
+      Tok::PropertyName => "tok-propertyName",
+      Tok::VariableName => "tok-variableName",
+      Tok::LabelName => "tok-labelName",
+      Tok::Meta => "tok-meta",
+      Tok::Heading => "tok-heading",
+      Tok::Emphasis => "tok-emphasis",
+      Tok::Strong => "tok-strong",
+      Tok::Link => "tok-link",
+      Tok::Url => "tok-url",
+    }
+  }
+}
+
+/// `tokens` are sorted and don't overlap.
+fn render(code: &str, tokens: &[(Range<usize>, Tok)]) -> String {
+  let mut res = String::with_capacity(code.len());
+  let mut pos = 0;
+  for (range, tok) in tokens {
+    res.push_str(&escape_html(&code[pos..range.start]));
+    format_to!(res, "<span class=\"{}\">{}</span>", tok.class(), escape_html(&code[range.clone()]));
+    pos = range.end;
+  }
+  res.push_str(&escape_html(&code[pos..]));
+  res
+}
+
+struct Lexer<'a> {
+  code: &'a str,
+  pos: usize,
+  tokens: Vec<(Range<usize>, Tok)>,
+}
+
+impl<'a> Lexer<'a> {
+  fn rest(&self) -> &'a str {
+    &self.code[self.pos..]
+  }
+  fn at(&self, s: &str) -> bool {
+    self.rest().starts_with(s)
+  }
+  fn peek(&self) -> Option<char> {
+    self.rest().chars().next()
+  }
+  fn bump(&mut self) {
+    self.pos += self.peek().map_or(0, char::len_utf8)
+  }
+  fn at_line_start(&self) -> bool {
+    let before = self.code[..self.pos].trim_end_matches([' ', '\t']);
+    before.is_empty() || before.ends_with('\n')
+  }
+  /// Whether the rest of the line, past blanks, starts with `s`.
+  fn followed_by(&self, s: &str) -> bool {
+    self.rest().trim_start_matches([' ', '\t']).starts_with(s)
+  }
+
+  fn token(&mut self, start: usize, tok: Tok) {
+    if self.pos > start {
+      self.tokens.push((start..self.pos, tok))
+    }
+  }
+  /// Moves past `end`, or to the end of the code if there's none.
+  fn skip_past(&mut self, end: &str) {
+    self.pos = self.rest().find(end).map_or(self.code.len(), |it| self.pos + it + end.len())
+  }
+  fn line_comment(&mut self) {
+    let start = self.pos;
+    self.pos = self.rest().find('\n').map_or(self.code.len(), |it| self.pos + it);
+    self.token(start, Tok::Comment)
+  }
+  /// A string from `start` up to `close`, the opening quote is consumed.
+  fn string(&mut self, start: usize, close: &str, escapes: bool) {
+    while !self.rest().is_empty() {
+      if escapes && self.at("\\") {
+        self.bump();
+      } else if self.at(close) {
+        self.pos += close.len();
+        break;
+      }
+      self.bump();
+    }
+    self.token(start, Tok::String)
+  }
+  fn word(&mut self) -> &'a str {
+    let start = self.pos;
+    while self.peek().is_some_and(is_word) {
+      self.bump()
+    }
+    &self.code[start..self.pos]
+  }
+  /// Digits, a sign or a dot, followed by word characters and dots. Also
+  /// takes a sign after an exponent.
+  fn number(&mut self) {
+    let start = self.pos;
+    self.bump();
+    while let Some(c) = self.peek() {
+      let exponent = matches!(c, '+' | '-')
+        && self.code[..self.pos].ends_with(['e', 'E'])
+        && !self.code[start..].starts_with("0x");
+      if !(is_word(c) || c == '.' || exponent) {
+        break;
+      }
+      self.bump()
+    }
+    self.token(start, Tok::Number)
+  }
+}
+
+fn is_word(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+  "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+  "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+  "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use", "where",
+  "while", "yield",
+];
+
+const RUST_TYPES: &[&str] = &[
+  "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+  "i128", "isize", "f32", "f64",
+];
+
+fn rust(lx: &mut Lexer) {
+  while let Some(c) = lx.peek() {
+    let start = lx.pos;
+    match c {
+      '/' if lx.at("//") => lx.line_comment(),
+      '/' if lx.at("/*") => {
+        lx.skip_past("*/");
+        lx.token(start, Tok::Comment)
+      }
+      '#' if lx.at("#[") || lx.at("#![") => {
+        lx.skip_past("]");
+        lx.token(start, Tok::Meta)
+      }
+      '"' => {
+        lx.bump();
+        lx.string(start, "\"", true)
+      }
+      '\'' => rust_quote(lx, start),
+      '0'..='9' => lx.number(),
+      _ if is_word(c) => {
+        let word = lx.word();
+        let hashes = lx.rest().len() - lx.rest().trim_start_matches('#').len();
+        if matches!(word, "r" | "br" | "cr") && lx.rest()[hashes..].starts_with('"') {
+          lx.pos += hashes + 1;
+          lx.string(start, &format!("\"{}", "#".repeat(hashes)), false);
+          continue;
+        }
+        if matches!(word, "b" | "c") && lx.at("\"") {
+          lx.bump();
+          lx.string(start, "\"", true);
+          continue;
+        }
+        if word == "b" && lx.at("'") {
+          rust_quote(lx, start);
+          continue;
+        }
+        let tok = if RUST_KEYWORDS.contains(&word) {
+          Tok::Keyword
+        } else if matches!(word, "true" | "false") {
+          Tok::Bool
+        } else if lx.at("!") && !lx.at("!=") {
+          lx.bump();
+          Tok::MacroName
+        } else if RUST_TYPES.contains(&word) || word.starts_with(char::is_uppercase) {
+          Tok::TypeName
+        } else {
+          continue;
+        };
+        lx.token(start, tok)
+      }
+      _ => lx.bump(),
+    }
+  }
+}
+
+/// A char literal or a lifetime.
+fn rust_quote(lx: &mut Lexer, start: usize) {
+  lx.bump();
+  let mut chars = lx.rest().chars();
+  let (first, second) = (chars.next(), chars.next());
+  if first == Some('\\') || second == Some('\'') {
+    lx.string(start, "'", true)
+  } else {
+    lx.word();
+    lx.token(start, Tok::LabelName)
+  }
+}
+
+const PYTHON_KEYWORDS: &[&str] = &[
+  "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+  "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+  "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+fn python(lx: &mut Lexer) {
+  while let Some(c) = lx.peek() {
+    let start = lx.pos;
+    match c {
+      '#' => lx.line_comment(),
+      '"' | '\'' => python_string(lx, start),
+      '@' if lx.at_line_start() => {
+        lx.bump();
+        while lx.peek().is_some_and(|it| is_word(it) || it == '.') {
+          lx.bump()
+        }
+        lx.token(start, Tok::Meta)
+      }
+      '0'..='9' => lx.number(),
+      _ if is_word(c) => {
+        let word = lx.word();
+        let prefix = word.len() <= 2 && word.chars().all(|it| "rRbBfFuU".contains(it));
+        if prefix && (lx.at("\"") || lx.at("'")) {
+          python_string(lx, start);
+          continue;
+        }
+        let tok = match word {
+          "True" | "False" => Tok::Bool,
+          "None" => Tok::Atom,
+          _ if PYTHON_KEYWORDS.contains(&word) => Tok::Keyword,
+          _ => continue,
+        };
+        lx.token(start, tok)
+      }
+      _ => lx.bump(),
+    }
+  }
+}
+
+fn python_string(lx: &mut Lexer, start: usize) {
+  let quote = ["\"\"\"", "'''", "\"", "'"].into_iter().find(|it| lx.at(it)).unwrap();
+  lx.pos += quote.len();
+  lx.string(start, quote, true)
+}
+
+fn json(lx: &mut Lexer) {
+  while let Some(c) = lx.peek() {
+    let start = lx.pos;
+    match c {
+      '"' => {
+        lx.bump();
+        lx.string(start, "\"", true);
+        if lx.rest().trim_start().starts_with(':') {
+          lx.tokens.last_mut().unwrap().1 = Tok::PropertyName;
+        }
+      }
+      '-' | '0'..='9' => lx.number(),
+      _ if is_word(c) => {
+        let tok = match lx.word() {
+          "true" | "false" => Tok::Bool,
+          "null" => Tok::Atom,
+          _ => continue,
+        };
+        lx.token(start, tok)
+      }
+      _ => lx.bump(),
+    }
+  }
+}
+
+const SHELL_KEYWORDS: &[&str] = &[
+  "if", "then", "else", "elif", "fi", "case", "esac", "for", "select", "while", "until", "do",
+  "done", "in", "function", "time", "return", "local", "export", "readonly", "declare",
+];
+
+fn shell(lx: &mut Lexer) {
+  while let Some(c) = lx.peek() {
+    let start = lx.pos;
+    match c {
+      '#' if lx.code[..start].chars().next_back().is_none_or(char::is_whitespace) => {
+        lx.line_comment()
+      }
+      '\'' => {
+        lx.bump();
+        lx.string(start, "'", false)
+      }
+      '"' => {
+        lx.bump();
+        lx.string(start, "\"", true)
+      }
+      '$' => {
+        lx.bump();
+        if lx.at("{") {
+          lx.skip_past("}");
+        } else if lx.peek().is_some_and(|it| "?#@*!$-".contains(it)) {
+          lx.bump();
+        } else {
+          lx.word();
+        }
+        if lx.pos > start + 1 {
+          lx.token(start, Tok::VariableName)
+        }
+      }
+      _ if is_word(c) || c == '-' => {
+        while lx.peek().is_some_and(|it| is_word(it) || it == '-') {
+          lx.bump()
+        }
+        let word = &lx.code[start..lx.pos];
+        let tok = if SHELL_KEYWORDS.contains(&word) {
+          Tok::Keyword
+        } else if lx.at("=")
+          && !word.contains('-')
+          && !word.starts_with(|it: char| it.is_ascii_digit())
+        {
+          Tok::VariableName
+        } else {
+          continue;
+        };
+        lx.token(start, tok)
+      }
+      _ => lx.bump(),
+    }
+  }
+}
+
+fn toml(lx: &mut Lexer) {
+  while let Some(c) = lx.peek() {
+    let start = lx.pos;
+    match c {
+      '#' => lx.line_comment(),
+      '[' if lx.at_line_start() => {
+        lx.skip_past("]");
+        if lx.at("]") {
+          lx.bump();
+        }
+        lx.token(start, Tok::Heading)
+      }
+      '"' | '\'' => {
+        let quote = ["\"\"\"", "'''", "\"", "'"].into_iter().find(|it| lx.at(it)).unwrap();
+        lx.pos += quote.len();
+        lx.string(start, quote, c == '"');
+        if lx.followed_by("=") || lx.followed_by(".") {
+          lx.tokens.last_mut().unwrap().1 = Tok::PropertyName;
+        }
+      }
+      // Keys, numbers and dates look much the same.
+      _ if is_word(c) || matches!(c, '+' | '-') => {
+        while lx.peek().is_some_and(|it| is_word(it) || matches!(it, '+' | '-' | '.' | ':')) {
+          lx.bump()
+        }
+        let word = &lx.code[start..lx.pos];
+        let tok = if lx.followed_by("=") {
+          Tok::PropertyName
+        } else if matches!(word, "true" | "false") {
+          Tok::Bool
+        } else if word.trim_start_matches(['+', '-']).starts_with(|it: char| it.is_ascii_digit())
+          || matches!(word.trim_start_matches(['+', '-']), "inf" | "nan")
+        {
+          Tok::Number
+        } else {
+          continue;
+        };
+        lx.token(start, tok)
+      }
+      _ => lx.bump(),
+    }
+  }
+}
+
+/// Follows the matches of the parser, the innermost container with a token
+/// kind decides the kind of the text.
+fn djot(code: &str) -> Vec<(Range<usize>, Tok)> {
+  let mut p = block::Tokenizer::new(code, ParseOpts::default());
+  p.parse();
+  let mut res = Vec::new();
+  push_token(&mut res, 0..p.front_matter, Some(Tok::Meta));
+  let mut stack: Vec<(Comp, Option<Tok>)> = Vec::new();
+  let mut pos = p.front_matter;
+  for m in &p.matches {
+    let top = stack.last().copied();
+    let inherited = top.and_then(|it| it.1);
+    // Closers of links overlap the openers of destinations.
+    let range = m.range.start.max(pos)..m.range.end.min(code.len());
+    // Text between fences is all matched, elsewhere spaces and such aren't.
+    let gap = if matches!(top, Some((Comp::CodeBlock, _))) { None } else { inherited };
+    push_token(&mut res, pos..range.start.min(code.len()), gap);
+    let tok = match m.a {
+      Annot::Add(Comp::CodeBlock) | Annot::Sub(Comp::CodeBlock) => Some(Tok::Meta),
+      Annot::Add(comp) => djot_comp(comp).or(inherited),
+      Annot::Sub(_) => inherited,
+      Annot::Atom(atom) => djot_atom(atom).or(inherited),
+    };
+    push_token(&mut res, range.clone(), tok);
+    match m.a {
+      Annot::Add(comp) => stack.push((comp, djot_comp(comp).or(inherited))),
+      Annot::Sub(_) => {
+        stack.pop();
+      }
+      Annot::Atom(_) => (),
+    }
+    pos = pos.max(range.end);
+  }
+  res
+}
+
+/// Adds a token, merging it into the previous one if they touch.
+fn push_token(tokens: &mut Vec<(Range<usize>, Tok)>, range: Range<usize>, tok: Option<Tok>) {
+  let Some(tok) = tok else { return };
+  if range.is_empty() {
+    return;
+  }
+  match tokens.last_mut() {
+    Some(last) if last.1 == tok && last.0.end == range.start => last.0.end = range.end,
+    _ => tokens.push((range, tok)),
+  }
+}
+
+fn djot_comp(comp: Comp) -> Option<Tok> {
+  let res = match comp {
+    Comp::Heading => Tok::Heading,
+    Comp::Strong => Tok::Strong,
+    Comp::Emph => Tok::Emphasis,
+    Comp::Verbatim | Comp::CodeBlock => Tok::String,
+    Comp::Linktext | Comp::Imagetext | Comp::Reference => Tok::Link,
+    Comp::Destination | Comp::Url | Comp::Email => Tok::Url,
+    Comp::Attributes | Comp::BlockAttributes => Tok::Meta,
+    _ => return None,
+  };
+  Some(res)
+}
+
+fn djot_atom(atom: Atom) -> Option<Tok> {
+  let res = match atom {
+    Atom::Escape => Tok::String2,
+    Atom::Emoji => Tok::Atom,
+    Atom::ReferenceKey => Tok::Link,
+    Atom::ReferenceValue => Tok::Url,
+    Atom::CodeLanguage => Tok::Meta,
+    _ => return None,
+  };
+  Some(res)
+}
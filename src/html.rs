@@ -1,20 +1,103 @@
 use std::collections::BTreeMap;
 
 use crate::{
-  ast::{self, Attrs, Tag},
-  tree::get_string_content,
-  Document, HtmlOpts,
+  ast::{self, normalize_label, Attrs, Tag},
+  template::Vars,
+  text, Document, HtmlOpts, Standalone, TocEntry,
 };
 
 pub(crate) fn convert(opts: &HtmlOpts, doc: &Document) -> String {
   let refs = &doc.references;
   let mut ctx = Ctx { opts, refs, res: String::new() };
   ctx.render_doc(doc);
-  ctx.res
+  let toc = opts.toc.map(|depth| render_toc(&doc.toc(), depth)).filter(|it| !it.is_empty());
+  match (&opts.standalone, toc) {
+    (Some(standalone), toc) => render_standalone(standalone, &doc.metadata, toc, ctx.res),
+    (None, Some(toc)) => format!("<nav id=\"toc\">\n{toc}</nav>\n{}", ctx.res),
+    (None, None) => ctx.res,
+  }
+}
+
+/// Nested lists of links to the headings up to level `depth`.
+fn render_toc(entries: &[TocEntry], depth: u32) -> String {
+  let mut res = String::new();
+  for entry in entries.iter().filter(|it| it.level <= depth) {
+    let (id, text) = (escape_html(&entry.id), escape_html(&entry.text));
+    res.push_str(&format!("<li><a href=\"#{id}\">{text}</a>"));
+    let children = render_toc(&entry.children, depth);
+    if !children.is_empty() {
+      res.push('\n');
+      res.push_str(&children);
+    }
+    res.push_str("</li>\n");
+  }
+  if res.is_empty() {
+    return res;
+  }
+  format!("<ul>\n{res}</ul>\n")
+}
+
+/// Variables override the metadata, and `title` and `css` override both.
+fn render_standalone(
+  standalone: &Standalone,
+  metadata: &ast::Metadata,
+  toc: Option<String>,
+  body: String,
+) -> String {
+  let escape = |values: &[String]| values.iter().map(|it| escape_html(it)).collect::<Vec<_>>();
+  let mut vars = Vars::new();
+  for (k, v) in metadata {
+    let values = match v {
+      serde_json::Value::Array(items) => items.iter().filter_map(metadata_text).collect(),
+      _ => metadata_text(v).into_iter().collect::<Vec<_>>(),
+    };
+    vars.insert(k.clone(), escape(&values));
+  }
+  for (k, v) in &standalone.variables {
+    vars.insert(k.clone(), escape(v));
+  }
+  if let Some(title) = &standalone.title {
+    vars.insert("title".to_string(), escape(std::slice::from_ref(title)));
+  }
+  if !standalone.css.is_empty() {
+    vars.insert("css".to_string(), escape(&standalone.css));
+  }
+  if let Some(toc) = toc {
+    vars.insert("toc".to_string(), vec![toc.trim_end().to_string()]);
+  }
+  vars.insert("body".to_string(), vec![body]);
+  standalone.template.render(&vars)
+}
+
+fn metadata_text(value: &serde_json::Value) -> Option<String> {
+  match value {
+    serde_json::Value::Null => None,
+    serde_json::Value::String(text) => Some(text.clone()),
+    _ => Some(value.to_string()),
+  }
+}
+
+/// Escapes for both text and attribute values.
+pub(crate) fn escape_html(s: &str) -> String {
+  let mut res = String::with_capacity(s.len());
+  escape_html_to(&mut res, s, true);
+  res
+}
+
+/// Like djot.lua, only attribute values get their quotes escaped.
+fn escape_html_to(res: &mut String, s: &str, attr: bool) {
+  for c in s.chars() {
+    match c {
+      '<' => res.push_str("&lt;"),
+      '>' => res.push_str("&gt;"),
+      '&' => res.push_str("&amp;"),
+      '"' if attr => res.push_str("&quot;"),
+      _ => res.push(c),
+    }
+  }
 }
 
 struct Ctx<'a> {
-  #[allow(unused)]
   opts: &'a HtmlOpts,
   refs: &'a BTreeMap<String, ast::ReferenceDefinition>,
   res: String,
@@ -27,7 +110,18 @@ impl<'a> Ctx<'a> {
   }
   fn render(&mut self, tag: &Tag) {
     match tag {
-      Tag::Heading(_) => todo!(),
+      Tag::Section(section) => {
+        self.render_tag("section", &section.attrs);
+        self.out("\n");
+        self.render_children(&section.children);
+        self.out("</section>\n");
+      }
+      Tag::Heading(heading) => {
+        let tag_name = format!("h{}", heading.level);
+        self.render_tag(&tag_name, &heading.attrs);
+        self.render_children(&heading.children);
+        self.out(&format!("</{tag_name}>\n"));
+      }
       Tag::Para(para) => {
         self.render_tag("p", &para.attrs);
         self.render_children(&para.children);
@@ -36,24 +130,30 @@ impl<'a> Ctx<'a> {
       }
       Tag::Link(link) => {
         let mut attrs = Attrs::new();
-        let dest = self.resolve_reference(link.destination.as_deref(), link.reference.as_deref());
+        let (dest, ref_attrs) =
+          self.resolve_reference(link.destination.as_deref(), link.reference.as_deref());
         if let Some(dest) = dest {
           attrs.insert("href".to_string(), dest);
         }
+        attrs.extend(ref_attrs);
+        attrs.extend(link.attrs.clone());
         self.render_tag("a", &attrs);
         self.render_children(&link.children);
         self.out("</a>");
       }
       Tag::Image(image) => {
         let mut attrs = Attrs::new();
-        let alt_text = get_string_content(&image.children);
+        let alt_text = text::inline_text(&image.children);
         if !alt_text.is_empty() {
           attrs.insert("alt".to_string(), alt_text);
         }
-        let dest = self.resolve_reference(image.destination.as_deref(), image.reference.as_deref());
+        let (dest, ref_attrs) =
+          self.resolve_reference(image.destination.as_deref(), image.reference.as_deref());
         if let Some(dest) = dest {
           attrs.insert("src".to_string(), dest);
         }
+        attrs.extend(ref_attrs);
+        attrs.extend(image.attrs.clone());
         self.render_tag("img", &attrs)
       }
       Tag::CodeBlock(code_block) => {
@@ -63,7 +163,14 @@ impl<'a> Ctx<'a> {
           attrs.insert("class".to_string(), format!("language-{lang}"));
         }
         self.render_tag("code", &attrs);
-        self.out_escape_html(&code_block.text);
+        let highlighted = match (&self.opts.highlighter, &code_block.lang) {
+          (Some(highlighter), Some(lang)) => highlighter.highlight(lang, &code_block.text),
+          _ => None,
+        };
+        match highlighted {
+          Some(html) => self.out(&html),
+          None => self.out_escape_html(&code_block.text),
+        }
         self.out("</code></pre>\n");
       }
       Tag::Strong(strong) => {
@@ -157,32 +264,36 @@ impl<'a> Ctx<'a> {
     for (k, v) in attrs {
       self.out(" ");
       self.out(k);
-      self.out("=");
-      self.out(&format!("{v:?}"));
+      self.out("=\"");
+      escape_html_to(&mut self.res, v, true);
+      self.out("\"");
     }
     self.out(">");
   }
 
+  /// The destination, and the attributes of the reference definition which
+  /// the link's own attributes override.
   fn resolve_reference(
     &self,
     destination: Option<&str>,
     reference: Option<&str>,
-  ) -> Option<String> {
+  ) -> (Option<String>, Attrs) {
     if let Some(destination) = destination {
-      return Some(destination.to_string());
+      return (Some(destination.to_string()), Attrs::new());
     }
     if let Some(reference) = reference {
-      if let Some(reference_definition) = self.refs.get(reference) {
-        return Some(reference_definition.destination.clone());
+      if let Some(reference_definition) = self.refs.get(&normalize_label(reference)) {
+        let destination = reference_definition.destination.clone();
+        return (Some(destination), reference_definition.attrs.clone());
       }
     }
-    None
+    (None, Attrs::new())
   }
 
   fn out(&mut self, s: &str) {
     self.res.push_str(s)
   }
   fn out_escape_html(&mut self, s: &str) {
-    self.res.push_str(s)
+    escape_html_to(&mut self.res, s, false)
   }
 }
@@ -1,19 +1,21 @@
 use std::{
   collections::{BTreeMap, HashMap},
   ops::Range,
+  sync::Arc,
 };
 
 use crate::{
   annot::{Annot, Atom, Comp},
   attribute,
-  patterns::{find_at, is_space, PatMatch},
+  diagnostics::Warning,
+  patterns::{find_at, is_space, pat, PatMatch, Pattern},
   Match, ParseOpts,
 };
 
 #[derive(Default)]
 pub struct Tokenizer {
   opts: ParseOpts,
-  subject: String,
+  subject: Arc<str>,
   matches: BTreeMap<usize, Match>,
   openers: HashMap<u8, Vec<Opener>>,
   verbatim: usize,
@@ -24,6 +26,7 @@ pub struct Tokenizer {
   allow_attributes: bool,
   attribute_tokenizer: Option<attribute::Tokenizer>,
   attribute_start: usize,
+  pub(crate) warnings: Vec<Warning>,
 }
 
 #[derive(Debug, Clone)]
@@ -40,7 +43,7 @@ impl Opener {
 }
 
 // allow up to 3 captures...
-fn bounded_find(subj: &str, patt: &'static str, startpos: usize, endpos: usize) -> PatMatch {
+fn bounded_find(subj: &str, patt: &Pattern, startpos: usize, endpos: usize) -> PatMatch {
   let mut m = find_at(subj, patt, startpos);
   if m.end > endpos {
     m = PatMatch::default()
@@ -48,8 +51,17 @@ fn bounded_find(subj: &str, patt: &'static str, startpos: usize, endpos: usize)
   m
 }
 
+// `{+` or `+}`
+fn has_brace(subject: &str, pos: usize) -> PatMatch {
+  let open = match pos.checked_sub(1) {
+    Some(prev) => find_at(subject, pat!("^%{"), prev),
+    None => PatMatch::default(),
+  };
+  open.or_else(|| find_at(subject, pat!("^%}"), pos + 1))
+}
+
 impl Tokenizer {
-  pub fn new(subject: String, opts: ParseOpts) -> Tokenizer {
+  pub fn new(subject: Arc<str>, opts: ParseOpts) -> Tokenizer {
     let mut res = Tokenizer::default();
     res.allow_attributes = true;
     res.subject = subject;
@@ -63,7 +75,12 @@ impl Tokenizer {
   }
 
   fn add_opener(&mut self, name: u8, opener: Opener) {
-    self.openers.entry(name).or_default().push(opener)
+    let openers = self.openers.entry(name).or_default();
+    if openers.len() >= self.opts.max_nesting.max(1) {
+      // The oldest opener stays literal text.
+      openers.remove(0);
+    }
+    openers.push(opener)
   }
 
   fn clear_openers(&mut self, startpos: usize, endpos: usize) {
@@ -113,7 +130,7 @@ impl Tokenizer {
   ) -> usize {
     debug_assert!(self.subject[pos..].as_bytes().starts_with(&[c]));
 
-    let mut can_open = find_at(&self.subject, "^%S", pos + 1).is_match;
+    let mut can_open = find_at(&self.subject, pat!("^%S"), pos + 1).is_match;
     let mut can_close = !self.subject[..pos].ends_with(is_space);
     let has_open_marker =
       pos != 0 && self.matches.get(&(pos - 1)).map_or(false, |it| it.is(Atom::OpenMarker));
@@ -169,7 +186,7 @@ impl Tokenizer {
   fn matchers(&mut self, c: u8, pos: usize, endpos: usize) -> Option<usize> {
     match c {
       b'`' => {
-        let m = bounded_find(&self.subject, "^`*", pos, endpos);
+        let m = bounded_find(&self.subject, pat!("^`*"), pos, endpos);
         if !m.is_match {
           return None;
         }
@@ -182,7 +199,7 @@ impl Tokenizer {
         return Some(m.end);
       }
       b'\\' => {
-        let m = bounded_find(&self.subject, "^[ \t]*\r?\n", pos + 1, endpos);
+        let m = bounded_find(&self.subject, pat!("^[ \t]*\r?\n"), pos + 1, endpos);
         self.add_match(pos..pos + 1, Atom::Escape);
 
         if m.is_match {
@@ -204,13 +221,13 @@ impl Tokenizer {
           self.add_match(pos + 1..m.end, Atom::Hardbreak);
           return Some(m.end);
         } else {
-          let m = bounded_find(&self.subject, "^[%p ]", pos + 1, endpos);
+          let m = bounded_find(&self.subject, pat!("^[%p ]"), pos + 1, endpos);
           if !m.is_match {
             self.add_match(pos..pos + 1, Atom::Str);
             return Some(pos + 1);
           } else {
             self.add_match(pos..pos + 1, Atom::Escape);
-            if find_at(&self.subject, "^ ", pos + 1).is_match {
+            if find_at(&self.subject, pat!("^ "), pos + 1).is_match {
               self.add_match(pos + 1..m.end, Atom::Nbsp)
             } else {
               self.add_match(pos + 1..m.end, Atom::Str)
@@ -220,10 +237,10 @@ impl Tokenizer {
         }
       }
       b'<' => {
-        let url = bounded_find(&self.subject, "^%<[^<>%s]+%>", pos, endpos);
+        let url = bounded_find(&self.subject, pat!("^%<[^<>%s]+%>"), pos, endpos);
         if url.is_match {
-          let is_url = bounded_find(&self.subject, "^%a+:", pos + 1, url.end).is_match;
-          let is_email = bounded_find(&self.subject, "^[^:]+%@", pos + 1, url.end).is_match;
+          let is_url = bounded_find(&self.subject, pat!("^%a+:"), pos + 1, url.end).is_match;
+          let is_email = bounded_find(&self.subject, pat!("^[^:]+%@"), pos + 1, url.end).is_match;
           if is_email {
             self.add_match(url.start..url.start + 1, Comp::Email.add());
             self.add_match(url.start + 1..url.end - 1, Atom::Str);
@@ -241,7 +258,7 @@ impl Tokenizer {
       b'~' => Some(self.between_matched(pos, b'~', Comp::Subscript, Atom::Str)),
       b'^' => Some(self.between_matched(pos, b'^', Comp::Superscript, Atom::Str)),
       b'[' => {
-        let m = bounded_find(&self.subject, "^%^([^]]+)%]", pos + 1, endpos);
+        let m = bounded_find(&self.subject, pat!("^%^([^]]+)%]"), pos + 1, endpos);
         if m.is_match {
           self.add_match(pos..m.end, Atom::FootnoteReference);
           return Some(m.end);
@@ -276,13 +293,13 @@ impl Tokenizer {
             // remove from openers
             self.clear_openers(opener.range.start, pos);
             return Some(pos + 1);
-          } else if bounded_find(&self.subject, "^[%[]", pos + 1, endpos).is_match {
+          } else if bounded_find(&self.subject, pat!("^[%[]"), pos + 1, endpos).is_match {
             opener.annot = "reference_link";
             opener.sub_range.start = pos; // intermediate ]
             opener.sub_range.end = pos + 2; // intermediate [
             self.add_match(pos..pos + 2, Atom::Str);
             return Some(pos + 2);
-          } else if bounded_find(&self.subject, "^[(]", pos + 1, endpos).is_match {
+          } else if bounded_find(&self.subject, pat!("^[(]"), pos + 1, endpos).is_match {
             opener.annot = "explicit_link";
             opener.sub_range.start = pos; // intermediate ]
             opener.sub_range.end = pos + 2; // intermediate (
@@ -290,7 +307,7 @@ impl Tokenizer {
             self.destination = true;
             self.add_match(pos..pos + 2, Atom::Str);
             return Some(pos + 2);
-          } else if bounded_find(&self.subject, "^%{", pos + 1, endpos).is_match {
+          } else if bounded_find(&self.subject, pat!("^%{"), pos + 1, endpos).is_match {
             let opener = opener.clone();
             // assume this is attributes, bracketed span
             self.add_match(opener.range.clone(), Comp::Span.add());
@@ -356,7 +373,7 @@ impl Tokenizer {
           self.add_match(pos..pos + 1, Atom::OpenMarker);
           return Some(pos + 1);
         } else if self.allow_attributes {
-          self.attribute_tokenizer = Some(attribute::Tokenizer::new(self.subject.clone()));
+          self.attribute_tokenizer = Some(attribute::Tokenizer::new(Arc::clone(&self.subject)));
           self.attribute_start = pos;
           return Some(pos);
         } else {
@@ -368,7 +385,7 @@ impl Tokenizer {
         }
       }
       b':' => {
-        let m = bounded_find(&self.subject, "^%:[%w_+-]+%:", pos, endpos);
+        let m = bounded_find(&self.subject, pat!("^%:[%w_+-]+%:"), pos, endpos);
         if m.is_match {
           self.add_match(m.start..m.end, Atom::Emoji);
           return Some(m.end);
@@ -377,29 +394,18 @@ impl Tokenizer {
           return Some(pos + 1);
         }
       }
-      b'+' => Some(self.between_matched_with_open_test(
-        pos,
-        b'+',
-        Comp::Insert,
-        Atom::Str,
-        |subject, pos| {
-          find_at(subject, "^%{", pos - 1).or_else(|| find_at(subject, "^%}", pos + 1))
-        },
-      )),
-      b'=' => Some(self.between_matched_with_open_test(
-        pos,
-        b'=',
-        Comp::Mark,
-        Atom::Str,
-        |subject, pos| {
-          find_at(subject, "^%{", pos - 1).or_else(|| find_at(subject, "^%}", pos + 1))
-        },
-      )),
-      b'\'' => todo!(),
+      b'+' => {
+        Some(self.between_matched_with_open_test(pos, b'+', Comp::Insert, Atom::Str, has_brace))
+      }
+      b'=' => {
+        Some(self.between_matched_with_open_test(pos, b'=', Comp::Mark, Atom::Str, has_brace))
+      }
+      // TODO: smart single quotes, for now `'` is literal.
+      b'\'' => None,
       b'"' => Some(self.between_matched(pos, b'"', Comp::DoubleQuoted, Atom::LeftDoubleQuote)),
       b'-' => {
         let subject = &self.subject[..];
-        if subject.as_bytes().get(pos - 1) == Some(&b'{')
+        if pos.checked_sub(1).and_then(|it| subject.as_bytes().get(it)) == Some(&b'{')
           || subject.as_bytes().get(pos + 1) == Some(&b'}')
         {
           return Some(self.between_matched_with_open_test(
@@ -407,13 +413,11 @@ impl Tokenizer {
             b'-',
             Comp::Delete,
             Atom::Str,
-            |subject, pos| {
-              find_at(subject, "^%{", pos - 1).or_else(|| find_at(subject, "^%}", pos + 1))
-            },
+            has_brace,
           ));
         }
 
-        let ep = find_at(subject, "^%-*", pos).end.min(endpos);
+        let ep = find_at(subject, pat!("^%-*"), pos).end.min(endpos);
         let mut hyphens = ep - pos;
         if subject.as_bytes().get(ep) == Some(&b'}') {
           // last hyphen is close del
@@ -452,7 +456,7 @@ impl Tokenizer {
         Some(pos)
       }
       b'.' => {
-        if bounded_find(&self.subject, "^%.%.", pos + 1, endpos).is_match {
+        if bounded_find(&self.subject, pat!("^%.%."), pos + 1, endpos).is_match {
           self.add_match(pos..pos + 3, Atom::Ellipses);
           return Some(pos + 3);
         }
@@ -469,8 +473,8 @@ impl Tokenizer {
 
   // Feed a slice to the parser, updating state.
   pub fn feed(&mut self, spos: usize, endpos: usize) {
-    let special = "[%]%[\\`{}_*()!<>~^:=+$\r\n'\".-]";
-    let subject = self.subject.clone();
+    let special = pat!("[%]%[\\`{}_*()!<>~^:=+$\r\n'\".-]");
+    let subject = Arc::clone(&self.subject);
     if spos < self.firstpos {
       self.firstpos = spos
     }
@@ -479,6 +483,20 @@ impl Tokenizer {
     }
     let mut pos = spos;
     while pos < endpos {
+      if self.matches.len() >= self.opts.max_matches {
+        // Out of budget, the rest is literal text.
+        if self.attribute_tokenizer.take().is_some() {
+          pos = self.attribute_start;
+        }
+        let eol = pos.max(subject[..endpos].trim_end_matches(['\r', '\n']).len());
+        if pos < eol {
+          self.add_match(pos..eol, Atom::Str);
+        }
+        if eol < endpos {
+          self.add_match(eol..endpos, Atom::Softbreak);
+        }
+        break;
+      }
       if let Some(mut attribute_tokenizer) = self.attribute_tokenizer.take() {
         let sp = pos;
         let m = bounded_find(&self.subject, special, pos, endpos);
@@ -523,7 +541,7 @@ impl Tokenizer {
         // i.e. we have something interesting at pos
         let c = subject.as_bytes()[pos];
         if c == b'\r' || c == b'\n' {
-          if c == b'\r' && bounded_find(&subject, "^[%n]", pos + 1, endpos).is_match {
+          if c == b'\r' && bounded_find(&subject, pat!("^[%n]"), pos + 1, endpos).is_match {
             self.add_match(pos..pos + 2, Atom::Softbreak);
             pos = pos + 2
           } else {
@@ -532,7 +550,7 @@ impl Tokenizer {
           }
         } else if self.verbatim > 0 {
           if c == b'`' {
-            let m = bounded_find(&subject, "^`+", pos, endpos);
+            let m = bounded_find(&subject, pat!("^`+"), pos, endpos);
             if m.is_match && m.end - pos == self.verbatim {
               // TODO: Check for raw attributes
               self.add_match(pos..m.end, self.verbatim_type.sub());
@@ -556,17 +574,22 @@ impl Tokenizer {
   }
 
   pub(crate) fn get_matches(&mut self) -> Vec<Match> {
+    while self.attribute_tokenizer.take().is_some() {
+      // unclosed attributes are text, like reparse_attributes in djot.lua
+      let start = std::mem::replace(&mut self.attribute_start, !0);
+      self.warnings.push(Warning::new(start, "unclosed attributes"));
+      self.allow_attributes = false;
+      self.feed(start, self.lastpos);
+    }
     let mut sorted: Vec<Match> = Vec::new();
     let mut m_last = Match::new(0..0, Atom::Ellipses); // TODO
-    for i in self.firstpos..=self.lastpos {
-      if let Some(m) = self.matches.get(&i) {
-        if m.is(Atom::Str) && m_last.is(Atom::Str) && m_last.range.end == m.range.start {
-          (*sorted.last_mut().unwrap()).range.end = m.range.end;
-          m_last.range.end = m.range.end;
-        } else {
-          sorted.push(m.clone());
-          m_last = m.clone()
-        }
+    for m in self.matches.range(self.firstpos..=self.lastpos).map(|(_, m)| m) {
+      if m.is(Atom::Str) && m_last.is(Atom::Str) && m_last.range.end == m.range.start {
+        (*sorted.last_mut().unwrap()).range.end = m.range.end;
+        m_last.range.end = m.range.end;
+      } else {
+        sorted.push(m.clone());
+        m_last = m.clone()
       }
     }
     if sorted.len() > 0 {
@@ -575,8 +598,9 @@ impl Tokenizer {
         sorted.pop();
       }
       if self.verbatim > 0 {
-        // unclosed verbatim
-        let e = sorted.last().unwrap().range.end;
+        // unclosed verbatim, closed at the last char like in djot.lua
+        let end = sorted.last().unwrap().range.end;
+        let e = self.subject[..end].char_indices().next_back().map_or(0, |(idx, _)| idx);
         sorted.push(Match::new(e..e, self.verbatim_type.sub()))
       }
     }
@@ -1,5 +1,9 @@
 // TODO: re-export everything.
 pub mod ast;
+pub mod emoji;
+pub mod events;
+pub mod highlight;
+pub mod template;
 
 mod annot;
 mod patterns;
@@ -7,39 +11,193 @@ mod block;
 mod inline;
 mod attribute;
 mod tree;
-mod emoji;
+mod reparse;
+mod metadata;
 mod html;
+mod djot;
+mod format;
+mod diagnostics;
+mod text;
+mod toc;
+#[cfg(feature = "fuzz")]
+#[doc(hidden)]
+pub mod fuzz;
 #[cfg(test)]
 mod sourcegen;
 
-use std::{collections::BTreeMap, ops::Range};
+use std::{collections::BTreeMap, fmt, ops::Range, sync::Arc};
 
 use crate::annot::Annot;
 
 #[derive(Debug, Default, Clone)]
 pub struct Document {
   pub children: Vec<ast::Tag>,
+  /// Definitions and headings by label, see [`ast::normalize_label`].
   pub references: BTreeMap<String, ast::ReferenceDefinition>,
+  /// The front matter: `key: value` lines between `---` lines at the start.
+  pub metadata: ast::Metadata,
   pub debug: String,
+  state: reparse::State,
 }
 
-#[derive(Default, Clone)]
+/// The `max_*` limits bound the work done on untrusted input. Whatever lies
+/// past a limit is kept as literal text instead of being parsed.
+#[derive(Debug, Clone)]
 pub struct ParseOpts {
   pub debug_matches: bool,
+  /// Record [`ast::SourceSpan`] of every node in its `pos` field.
+  pub source_positions: bool,
+  /// Containers nested deeper than this become a single [`ast::Str`] with
+  /// their source text. Also bounds the number of pending openers per
+  /// delimiter. The default keeps [`Document::to_json`] readable by
+  /// [`Document::from_json`].
+  pub max_nesting: usize,
+  /// Lines starting past this many bytes form one literal paragraph.
+  pub max_input_bytes: usize,
+  /// Once roughly this many matches are produced, the rest of the input is
+  /// literal text.
+  pub max_matches: usize,
+  /// Tokenize the inline content of paragraphs on several threads. The result
+  /// is the same as parsing serially. Ignored if `max_matches` is set.
+  pub parallel: bool,
+}
+
+impl Default for ParseOpts {
+  fn default() -> ParseOpts {
+    ParseOpts {
+      debug_matches: false,
+      source_positions: false,
+      max_nesting: 50,
+      max_input_bytes: usize::MAX,
+      max_matches: usize::MAX,
+      parallel: false,
+    }
+  }
 }
 
 #[derive(Default, Clone)]
-pub struct HtmlOpts {}
+pub struct HtmlOpts {
+  /// Wrap the output in a full HTML page.
+  pub standalone: Option<Standalone>,
+  /// Start with a `<nav>` listing the headings up to this level, see
+  /// [`Document::toc`].
+  pub toc: Option<u32>,
+  /// Marks up the code of code blocks with a language.
+  pub highlighter: Option<Arc<dyn highlight::Highlighter + Send + Sync>>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FormatOpts {
+  /// Reflow paragraphs into lines of at most this many chars, where they can
+  /// break.
+  pub width: Option<usize>,
+}
+
+/// A full HTML page around the document. The template gets the rendered
+/// document as `body`, the table of contents as `toc`, and the
+/// [`Document::metadata`], `title`, `css` and `variables` HTML-escaped.
+#[derive(Default, Clone)]
+pub struct Standalone {
+  pub template: template::Template,
+  pub title: Option<String>,
+  /// Stylesheets to link to.
+  pub css: Vec<String>,
+  pub variables: BTreeMap<String, Vec<String>>,
+}
+
+/// A heading in [`Document::toc`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+  pub level: u32,
+  /// The id of the heading's section.
+  pub id: String,
+  /// The heading without markup.
+  pub text: String,
+  /// The headings of the subsections.
+  pub children: Vec<TocEntry>,
+}
+
+/// A likely mistake in the source, see [`Document::diagnostics`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub span: ast::SourceSpan,
+  pub severity: Severity,
+  pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  /// The output is likely not what was meant, like a link without target.
+  Error,
+  Warning,
+  /// Harmless, like an unused reference definition.
+  Hint,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+      Severity::Hint => "hint",
+    })
+  }
+}
+
+/// A bug in the parser: the tokenizers produced an inconsistent match stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+  /// Byte offset into the source where the problem was detected.
+  pub offset: usize,
+  pub message: String,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "internal parser error at byte {}: {}", self.offset, self.message)
+  }
+}
+
+impl std::error::Error for ParseError {}
 
 impl Document {
+  /// Never fails: constructs the parser can't handle are skipped.
   pub fn parse(text: &str) -> Document {
     Document::parse_opts(ParseOpts::default(), text)
   }
 
   pub fn parse_opts(opts: ParseOpts, text: &str) -> Document {
-    let mut p = block::Tokenizer::new(text.to_string(), opts);
-    p.parse();
-    tree::build(p)
+    Document::build(opts, text).0
+  }
+
+  /// Like [`Document::parse`], but reports internal errors of the parser
+  /// instead of skipping the offending constructs.
+  pub fn try_parse(text: &str) -> Result<Document, ParseError> {
+    Document::try_parse_opts(ParseOpts::default(), text)
+  }
+
+  pub fn try_parse_opts(opts: ParseOpts, text: &str) -> Result<Document, ParseError> {
+    match Document::build(opts, text) {
+      (doc, None) => Ok(doc),
+      (_, Some(err)) => Err(err),
+    }
+  }
+
+  /// Updates the document after replacing `edit` of the parsed text with
+  /// `new_text`, the same as parsing the new text with the same options.
+  ///
+  /// Only the top-level blocks around the edit are parsed again. A document
+  /// not coming from a parse counts as parsed from an empty text.
+  ///
+  /// # Panics
+  ///
+  /// If `edit` is out of bounds or not on char boundaries.
+  pub fn reparse(&mut self, edit: Range<usize>, new_text: &str) {
+    reparse::reparse(self, edit, new_text)
+  }
+
+  fn build(opts: ParseOpts, text: &str) -> (Document, Option<ParseError>) {
+    reparse::parse(opts, text)
   }
 
   pub fn to_html(&self) -> String {
@@ -50,20 +208,94 @@ impl Document {
     html::convert(opts, self)
   }
 
+  /// The outline of the sections, following their nesting.
+  pub fn toc(&self) -> Vec<TocEntry> {
+    toc::toc(&self.children)
+  }
+
+  /// Undefined, duplicate and unused references, unknown emoji and unclosed
+  /// attributes in the parsed text, in source order. A document not coming
+  /// from a parse has none.
+  pub fn diagnostics(&self) -> Vec<Diagnostic> {
+    diagnostics::check(self)
+  }
+
+  /// Djot which parses back to this document.
+  pub fn to_djot(&self) -> String {
+    djot::convert(self)
+  }
+
+  /// Just the text, without markup.
+  pub fn to_text(&self) -> String {
+    text::convert(self)
+  }
+
   pub fn to_json(&self) -> String {
     #[derive(serde::Serialize)]
     struct DocRepr<'a> {
       tag: &'static str,
       children: &'a [ast::Tag],
       references: &'a BTreeMap<String, ast::ReferenceDefinition>,
+      #[serde(skip_serializing_if = "ast::Metadata::is_empty")]
+      metadata: &'a ast::Metadata,
     }
     serde_json::to_string_pretty(&DocRepr {
       tag: "doc",
       children: self.children.as_slice(),
       references: &self.references,
+      metadata: &self.metadata,
     })
     .unwrap()
   }
+
+  pub fn from_json(json: &str) -> serde_json::Result<Document> {
+    #[derive(serde::Deserialize)]
+    struct DocRepr {
+      children: Vec<ast::Tag>,
+      #[serde(default)]
+      references: BTreeMap<String, ast::ReferenceDefinition>,
+      #[serde(default)]
+      metadata: ast::Metadata,
+    }
+    let repr: DocRepr = serde_json::from_str(json)?;
+    Ok(Document {
+      children: repr.children,
+      references: repr.references,
+      metadata: repr.metadata,
+      ..Document::default()
+    })
+  }
+}
+
+/// Formats djot source: blocks are separated by one blank line, reference
+/// definitions go to the end, code blocks are fenced with backticks where they
+/// can be, emphasis markers lose their braces, as in `{_emph_}`, where they
+/// don't need them, and adjacent attributes are merged and written in a
+/// canonical order: id, classes, then the rest by key. The rest of the text
+/// of each block is kept, save for line breaks within paragraphs with
+/// [`FormatOpts::width`]. Attributes with comments are kept as written. The
+/// parser knows neither lists nor fences longer than three characters, so
+/// there are no bullets or fence lengths to normalize yet.
+///
+/// The result renders to the same HTML, save for the order of attributes and,
+/// when reflowing, line breaks within paragraphs taken for spaces. Debug
+/// builds check this.
+pub fn format(text: &str, opts: &FormatOpts) -> String {
+  format::format(text, opts)
+}
+
+/// A transformation of the parsed document, applied before rendering.
+///
+/// See the `--filter` flag of the CLI for filters which are external programs
+/// working on the JSON AST.
+pub trait Filter {
+  fn filter(&mut self, doc: &mut Document);
+}
+
+impl<F: FnMut(&mut Document)> Filter for F {
+  fn filter(&mut self, doc: &mut Document) {
+    self(doc)
+  }
 }
 
 #[derive(Debug, Clone)]
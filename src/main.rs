@@ -1,47 +1,477 @@
-use std::path::PathBuf;
+use std::{
+  io::Write,
+  path::{Path, PathBuf},
+  process::{Command, ExitCode, Stdio},
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use lexopt::{Arg::Long, Arg::Short, Arg::Value};
 
-fn main() -> anyhow::Result<()> {
-  let mut matches = false;
-  let mut ast = false;
-  let mut files = Vec::new();
+const USAGE: &str = "\
+usage: djot [options] [FILE]...
+       djot check [FILE]...
+       djot fmt [--check] [--width N] [FILE]...
+
+Converts djot FILEs, or stdin if there are none. See `djot check --help` and
+`djot fmt --help` for checking and formatting them instead.
+
+options:
+  -t, --to FORMAT      html (default), json, djot, text or matches
+  -a, --ast            same as --to json
+  -m, --matches        same as --to matches
+  -o, --output PATH    write to PATH instead of stdout; with several inputs,
+                       or if PATH is a directory or ends with `/`, write each
+                       input to a file mirroring its path under PATH
+  -p, --sourcepos      include source positions in the AST
+  -f, --filter PROG    pipe the JSON AST through PROG, can be repeated
+  -s, --standalone     write a full HTML page instead of a fragment
+      --template FILE  use FILE as the page template, implies --standalone
+      --title TEXT     page title, defaults to the `title` in the front matter
+                       or the input file name
+      --css URL        link a stylesheet, can be repeated
+  -V, --variable K=V   set the template variable K, can be repeated
+      --toc            start with a table of contents
+      --toc-depth N    include headings up to level N in it, 3 by default
+  -h, --help           print this help";
+
+const CHECK_USAGE: &str = "\
+usage: djot check [FILE]...
+
+Reports likely mistakes in djot FILEs, or stdin if there are none: undefined,
+duplicate and unused references, unknown emoji and unclosed attributes. Fails
+if there are errors or warnings.
+
+options:
+  -h, --help  print this help";
+
+const FMT_USAGE: &str = "\
+usage: djot fmt [options] [FILE]...
+
+Formats djot FILEs in place, or stdin to stdout if there are none. The text of
+blocks is kept, save for attributes, code fences and line breaks in paragraphs,
+and reference definitions go to the end. The result renders to the same HTML,
+up to line breaks in reflowed paragraphs.
+
+options:
+      --check    don't write, fail if some input isn't formatted
+  -w, --width N  reflow paragraphs to N columns, 0 keeps their lines; 80 by
+                 default
+  -h, --help     print this help";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+  Html,
+  Json,
+  Djot,
+  Text,
+  Matches,
+}
+
+impl Format {
+  fn parse(name: &str) -> anyhow::Result<Format> {
+    let res = match name {
+      "html" => Format::Html,
+      "json" | "ast" => Format::Json,
+      "djot" => Format::Djot,
+      "text" => Format::Text,
+      "matches" => Format::Matches,
+      _ => bail!("unknown format `{name}`, expected html, json, djot, text or matches"),
+    };
+    Ok(res)
+  }
+
+  fn extension(self) -> &'static str {
+    match self {
+      Format::Html => "html",
+      Format::Json => "json",
+      Format::Djot => "dj",
+      Format::Text => "txt",
+      Format::Matches => "matches",
+    }
+  }
+}
+
+struct Args {
+  to: Format,
+  sourcepos: bool,
+  filters: Vec<PathBuf>,
+  output: Option<PathBuf>,
+  standalone: bool,
+  template: Option<PathBuf>,
+  title: Option<String>,
+  css: Vec<String>,
+  variables: Vec<(String, String)>,
+  toc: Option<u32>,
+  files: Vec<PathBuf>,
+}
+
+fn main() -> ExitCode {
+  let mut args = std::env::args_os().skip(1).peekable();
+  if args.next_if(|it| it == "check").is_some() {
+    return check(lexopt::Parser::from_args(args));
+  }
+  if args.next_if(|it| it == "fmt").is_some() {
+    return fmt(lexopt::Parser::from_args(args));
+  }
+  let args = match parse_args(lexopt::Parser::from_args(args)) {
+    Ok(Some(args)) => args,
+    Ok(None) => {
+      println!("{USAGE}");
+      return ExitCode::SUCCESS;
+    }
+    Err(err) => {
+      eprintln!("error: {err:#}\n\n{USAGE}");
+      return ExitCode::from(2);
+    }
+  };
+  let mut ok = true;
+  for err in run(&args) {
+    eprintln!("error: {err:#}");
+    ok = false;
+  }
+  if ok {
+    ExitCode::SUCCESS
+  } else {
+    ExitCode::FAILURE
+  }
+}
+
+fn parse_args(mut parser: lexopt::Parser) -> anyhow::Result<Option<Args>> {
+  let mut args = Args {
+    to: Format::Html,
+    sourcepos: false,
+    filters: Vec::new(),
+    output: None,
+    standalone: false,
+    template: None,
+    title: None,
+    css: Vec::new(),
+    variables: Vec::new(),
+    toc: None,
+    files: Vec::new(),
+  };
+  while let Some(arg) = parser.next()? {
+    match arg {
+      Short('t') | Long("to") => args.to = Format::parse(&parser.value()?.to_string_lossy())?,
+      Short('m') | Long("matches") => args.to = Format::Matches,
+      Short('a') | Long("ast") => args.to = Format::Json,
+      Short('o') | Long("output") => args.output = Some(PathBuf::from(parser.value()?)),
+      Short('p') | Long("sourcepos") => args.sourcepos = true,
+      Short('f') | Long("filter") => args.filters.push(PathBuf::from(parser.value()?)),
+      Short('s') | Long("standalone") => args.standalone = true,
+      Long("template") => {
+        args.standalone = true;
+        args.template = Some(PathBuf::from(parser.value()?))
+      }
+      Long("title") => args.title = Some(string_value(&mut parser)?),
+      Long("css") => args.css.push(string_value(&mut parser)?),
+      Short('V') | Long("variable") => {
+        let var = string_value(&mut parser)?;
+        let Some((k, v)) = var.split_once('=') else { bail!("expected KEY=VALUE, got `{var}`") };
+        args.variables.push((k.to_string(), v.to_string()))
+      }
+      Long("toc") => args.toc = Some(args.toc.unwrap_or(3)),
+      Long("toc-depth") => {
+        let depth = string_value(&mut parser)?;
+        args.toc = Some(depth.parse().with_context(|| format!("invalid depth `{depth}`"))?)
+      }
+      Short('h') | Long("help") => return Ok(None),
+      Value(val) => args.files.push(PathBuf::from(val)),
+      _ => Err(arg.unexpected())?,
+    }
+  }
+  if args.standalone && args.to != Format::Html {
+    bail!("--standalone only applies to html output")
+  }
+  if args.toc.is_some() && args.to != Format::Html {
+    bail!("--toc only applies to html output")
+  }
+  Ok(Some(args))
+}
+
+/// The value of an option which has to be valid UTF-8.
+fn string_value(parser: &mut lexopt::Parser) -> Result<String, lexopt::Error> {
+  Ok(parser.value()?.into_string()?)
+}
+
+/// Prints the diagnostics of every input, failing on errors and warnings.
+fn check(parser: lexopt::Parser) -> ExitCode {
+  let files = match parse_check_args(parser) {
+    Ok(Some(files)) => files,
+    Ok(None) => {
+      println!("{CHECK_USAGE}");
+      return ExitCode::SUCCESS;
+    }
+    Err(err) => {
+      eprintln!("error: {err:#}\n\n{CHECK_USAGE}");
+      return ExitCode::from(2);
+    }
+  };
+  let inputs = if files.is_empty() { vec![None] } else { files.iter().map(Some).collect() };
+  let mut ok = true;
+  for file in inputs {
+    let content = match file {
+      Some(file) => {
+        std::fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))
+      }
+      None => std::io::read_to_string(std::io::stdin()).context("failed to read stdin"),
+    };
+    let content = match content {
+      Ok(it) => it,
+      Err(err) => {
+        eprintln!("error: {err:#}");
+        ok = false;
+        continue;
+      }
+    };
+    let name = file.map_or("<stdin>".to_string(), |it| it.display().to_string());
+    for diagnostic in djot::Document::parse(&content).diagnostics() {
+      let djot::Diagnostic { span, severity, message } = diagnostic;
+      println!("{name}:{}:{}: {severity}: {message}", span.start.line, span.start.col);
+      ok &= severity == djot::Severity::Hint;
+    }
+  }
+  if ok {
+    ExitCode::SUCCESS
+  } else {
+    ExitCode::FAILURE
+  }
+}
 
-  let mut parser = lexopt::Parser::from_env();
+fn parse_check_args(mut parser: lexopt::Parser) -> anyhow::Result<Option<Vec<PathBuf>>> {
+  let mut files = Vec::new();
   while let Some(arg) = parser.next()? {
     match arg {
-      Short('m') | Long("matches") => matches = true,
-      Short('a') | Long("ast") => ast = true,
-      Value(val) => files.push(val),
+      Short('h') | Long("help") => return Ok(None),
+      Value(val) => files.push(PathBuf::from(val)),
       _ => Err(arg.unexpected())?,
     }
   }
+  Ok(Some(files))
+}
 
-  let mut inputs = Vec::new();
-  if files.is_empty() {
-    let content = std::io::read_to_string(std::io::stdin()).context("failed to read stdin")?;
-    inputs.push(content)
+struct FmtArgs {
+  check: bool,
+  width: Option<usize>,
+  files: Vec<PathBuf>,
+}
+
+/// Formats every input, or with `--check` fails if some isn't formatted.
+fn fmt(parser: lexopt::Parser) -> ExitCode {
+  let args = match parse_fmt_args(parser) {
+    Ok(Some(args)) => args,
+    Ok(None) => {
+      println!("{FMT_USAGE}");
+      return ExitCode::SUCCESS;
+    }
+    Err(err) => {
+      eprintln!("error: {err:#}\n\n{FMT_USAGE}");
+      return ExitCode::from(2);
+    }
+  };
+  let opts = djot::FormatOpts { width: args.width };
+  let inputs =
+    if args.files.is_empty() { vec![None] } else { args.files.iter().map(Some).collect() };
+  let mut ok = true;
+  for file in inputs {
+    if let Err(err) = fmt_input(&args, &opts, file.map(PathBuf::as_path)) {
+      eprintln!("error: {err:#}");
+      ok = false;
+    }
+  }
+  if ok {
+    ExitCode::SUCCESS
   } else {
-    for file in files {
-      let path = PathBuf::from(file);
-      let content = std::fs::read_to_string(&path)
-        .with_context(|| format!("failed to read {}", path.display()))?;
-      inputs.push(content)
+    ExitCode::FAILURE
+  }
+}
+
+fn parse_fmt_args(mut parser: lexopt::Parser) -> anyhow::Result<Option<FmtArgs>> {
+  let mut args = FmtArgs { check: false, width: Some(80), files: Vec::new() };
+  while let Some(arg) = parser.next()? {
+    match arg {
+      Long("check") => args.check = true,
+      Short('w') | Long("width") => {
+        let width = string_value(&mut parser)?;
+        let width: usize = width.parse().with_context(|| format!("invalid width `{width}`"))?;
+        args.width = Some(width).filter(|&it| it > 0)
+      }
+      Short('h') | Long("help") => return Ok(None),
+      Value(val) => args.files.push(PathBuf::from(val)),
+      _ => Err(arg.unexpected())?,
+    }
+  }
+  Ok(Some(args))
+}
+
+/// Formats `file` in place, or stdin to stdout.
+fn fmt_input(args: &FmtArgs, opts: &djot::FormatOpts, file: Option<&Path>) -> anyhow::Result<()> {
+  let name = file.map_or("stdin".to_string(), |it| it.display().to_string());
+  let content = match file {
+    Some(file) => std::fs::read_to_string(file),
+    None => std::io::read_to_string(std::io::stdin()),
+  }
+  .with_context(|| format!("failed to read {name}"))?;
+  let formatted = djot::format(&content, opts);
+  if args.check {
+    if formatted != content {
+      bail!("{name} is not formatted")
+    }
+    return Ok(());
+  }
+  if file.is_some() && formatted == content {
+    return Ok(());
+  }
+  write_output(file, &formatted)
+}
+
+/// Converts every input, returning the errors of those which failed.
+fn run(args: &Args) -> Vec<anyhow::Error> {
+  let html_opts = match html_opts(args) {
+    Ok(it) => it,
+    Err(err) => return vec![err],
+  };
+  if args.files.is_empty() {
+    let res = std::io::read_to_string(std::io::stdin())
+      .context("failed to read stdin")
+      .and_then(|content| convert(args, &html_opts, &content, None))
+      .and_then(|output| write_output(args.output.as_deref(), &output));
+    return res.err().into_iter().collect();
+  }
+
+  let out_dir = args.output.as_deref().filter(|it| {
+    args.files.len() > 1 || it.is_dir() || it.as_os_str().to_string_lossy().ends_with('/')
+  });
+  let base = common_dir(&args.files);
+  let mut errors = Vec::new();
+  for file in &args.files {
+    let output = match out_dir {
+      Some(dir) => {
+        let path = dir.join(file.strip_prefix(&base).unwrap_or(file));
+        Some(path.with_extension(args.to.extension()))
+      }
+      None => args.output.clone(),
+    };
+    errors.extend(convert_file(args, &html_opts, file, output.as_deref()).err());
+  }
+  errors
+}
+
+fn html_opts(args: &Args) -> anyhow::Result<djot::HtmlOpts> {
+  if !args.standalone {
+    return Ok(djot::HtmlOpts { toc: args.toc, ..djot::HtmlOpts::default() });
+  }
+  let mut standalone = djot::Standalone::default();
+  if let Some(path) = &args.template {
+    let text = std::fs::read_to_string(path)
+      .with_context(|| format!("failed to read {}", path.display()))?;
+    standalone.template = djot::template::Template::parse(&text)
+      .with_context(|| format!("failed to parse {}", path.display()))?;
+  }
+  standalone.title = args.title.clone();
+  standalone.css = args.css.clone();
+  for (k, v) in &args.variables {
+    standalone.variables.entry(k.clone()).or_default().push(v.clone());
+  }
+  Ok(djot::HtmlOpts { standalone: Some(standalone), toc: args.toc, ..djot::HtmlOpts::default() })
+}
+
+fn convert_file(
+  args: &Args,
+  html_opts: &djot::HtmlOpts,
+  file: &Path,
+  output: Option<&Path>,
+) -> anyhow::Result<()> {
+  let content =
+    std::fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+  let res = convert(args, html_opts, &content, Some(file))
+    .with_context(|| format!("failed to convert {}", file.display()))?;
+  write_output(output, &res)
+}
+
+fn convert(
+  args: &Args,
+  html_opts: &djot::HtmlOpts,
+  content: &str,
+  file: Option<&Path>,
+) -> anyhow::Result<String> {
+  let opts = djot::ParseOpts {
+    debug_matches: args.to == Format::Matches,
+    source_positions: args.sourcepos,
+    ..djot::ParseOpts::default()
+  };
+  let mut doc = djot::Document::parse_opts(opts, content);
+  for filter in &args.filters {
+    doc = run_filter(filter, &doc)?;
+  }
+  let mut res = match args.to {
+    Format::Html => {
+      // Name the page after the file, unless it has a title.
+      let mut html_opts = html_opts.clone();
+      if let (Some(standalone), Some(file)) = (&mut html_opts.standalone, file) {
+        if standalone.title.is_none() && !doc.metadata.contains_key("title") {
+          standalone.title = file.file_stem().map(|it| it.to_string_lossy().into_owned());
+        }
+      }
+      doc.to_html_opts(&html_opts)
     }
+    Format::Json => doc.to_json(),
+    Format::Djot => doc.to_djot(),
+    Format::Text => doc.to_text(),
+    Format::Matches => doc.debug,
+  };
+  if !res.ends_with('\n') {
+    res.push('\n');
   }
+  Ok(res)
+}
 
-  let opts = djot::ParseOpts { debug_matches: matches };
-  for content in inputs {
-    let doc = djot::Document::parse_opts(opts.clone(), &content);
-    if matches {
-      println!("{}", doc.debug)
-    } else if ast {
-      println!("{}", doc.to_json())
-    } else {
-      println!("{}", doc.to_html())
+/// Writes to `path`, creating missing directories, or to stdout.
+fn write_output(path: Option<&Path>, output: &str) -> anyhow::Result<()> {
+  let Some(path) = path else {
+    let mut stdout = std::io::stdout().lock();
+    return stdout.write_all(output.as_bytes()).context("failed to write to stdout");
+  };
+  if let Some(dir) = path.parent().filter(|it| !it.as_os_str().is_empty()) {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+  }
+  std::fs::write(path, output).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// The deepest directory containing all `files`, to mirror their layout.
+fn common_dir(files: &[PathBuf]) -> PathBuf {
+  let mut res = files[0].parent().unwrap_or(Path::new("")).to_path_buf();
+  for file in &files[1..] {
+    while !file.starts_with(&res) {
+      if !res.pop() {
+        break;
+      }
     }
   }
+  res
+}
+
+/// Pipes the JSON AST through an external program, like djot.js filters.
+fn run_filter(filter: &Path, doc: &djot::Document) -> anyhow::Result<djot::Document> {
+  let mut child = Command::new(filter)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .with_context(|| format!("failed to run filter {}", filter.display()))?;
+
+  let json = doc.to_json();
+  let mut stdin = child.stdin.take().unwrap();
+  let writer = std::thread::spawn(move || stdin.write_all(json.as_bytes()));
+  let output = child.wait_with_output()?;
+  if !output.status.success() {
+    bail!("filter {} failed: {}", filter.display(), output.status)
+  }
+  writer.join().unwrap().with_context(|| format!("failed to write to {}", filter.display()))?;
 
-  Ok(())
+  let json = String::from_utf8(output.stdout)
+    .with_context(|| format!("filter {} produced invalid utf-8", filter.display()))?;
+  let mut res = djot::Document::from_json(&json)
+    .with_context(|| format!("filter {} produced invalid AST", filter.display()))?;
+  res.debug = doc.debug.clone();
+  Ok(res)
 }
@@ -0,0 +1,135 @@
+//! Front matter: a block of YAML between `---` lines at the very start of the
+//! document.
+//!
+//! Only a subset of YAML is understood: `key: value` pairs whose value is a
+//! scalar, a flow sequence like `[a, b]`, a JSON value, or a block sequence of
+//! `- item` lines below the key. Front matter using anything else is parsed as
+//! ordinary djot.
+use serde_json::Value;
+
+use crate::ast::Metadata;
+
+/// The end of the front matter starting `text` and its values.
+pub(crate) fn front_matter(text: &str) -> Option<(usize, Metadata)> {
+  let mut lines = text.split_inclusive('\n');
+  if lines.next()?.trim_end() != "---" {
+    return None;
+  }
+  let mut end = text.find('\n')? + 1;
+  let start = end;
+  for line in lines {
+    if matches!(line.trim_end(), "---" | "...") {
+      let metadata = parse(&text[start..end])?;
+      return Some((end + line.len(), metadata));
+    }
+    end += line.len();
+  }
+  None
+}
+
+fn parse(text: &str) -> Option<Metadata> {
+  let mut res = Metadata::new();
+  let mut list: Option<(String, Vec<Value>)> = None;
+  for line in text.lines() {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+      continue;
+    }
+    if line.starts_with([' ', '\t', '-']) {
+      let item = trimmed.strip_prefix('-').filter(|it| it.is_empty() || it.starts_with(' '))?;
+      list.as_mut()?.1.push(value(item.trim())?);
+      continue;
+    }
+    if let Some((key, items)) = list.take() {
+      res.insert(key, Value::Array(items));
+    }
+    let (key, rest) = line.split_once(':')?;
+    let key = key.trim();
+    if key.is_empty() || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+      return None;
+    }
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+      list = Some((key.to_string(), Vec::new()));
+    } else {
+      res.insert(key.to_string(), value(rest)?);
+    }
+  }
+  if let Some((key, items)) = list {
+    let value = if items.is_empty() { Value::Null } else { Value::Array(items) };
+    res.insert(key, value);
+  }
+  Some(res)
+}
+
+fn value(text: &str) -> Option<Value> {
+  if text.starts_with(['"', '[', '{']) {
+    if let Ok(value) = serde_json::from_str(text) {
+      return Some(value);
+    }
+  }
+  if let Some(items) = text.strip_prefix('[').and_then(|it| it.strip_suffix(']')) {
+    if items.trim().is_empty() {
+      return Some(Value::Array(Vec::new()));
+    }
+    return items.split(',').map(|it| scalar(it.trim())).collect::<Option<_>>().map(Value::Array);
+  }
+  scalar(text)
+}
+
+fn scalar(text: &str) -> Option<Value> {
+  if let Some(quoted) = text.strip_prefix('\'') {
+    return Some(Value::String(quoted.strip_suffix('\'')?.replace("''", "'")));
+  }
+  if text.starts_with(['"', '[', ']', '{', '}', '&', '*', '!', '|', '>', '%', '@', '`']) {
+    return None;
+  }
+  let text = match text.find(" #") {
+    Some(idx) => text[..idx].trim_end(),
+    None => text,
+  };
+  let res = match text {
+    "" | "~" | "null" => Value::Null,
+    "true" => Value::Bool(true),
+    "false" => Value::Bool(false),
+    _ => match text.parse::<serde_json::Number>() {
+      Ok(number) => Value::Number(number),
+      Err(_) => Value::String(text.to_string()),
+    },
+  };
+  Some(res)
+}
+
+/// Front matter which parses back to `metadata`.
+pub(crate) fn render(metadata: &Metadata) -> String {
+  let mut res = "---\n".to_string();
+  for (key, value) in metadata {
+    res.push_str(key);
+    res.push(':');
+    match value {
+      Value::Array(items) if !items.is_empty() => {
+        res.push('\n');
+        for item in items {
+          res.push_str(&format!("  - {}\n", render_value(item)));
+        }
+      }
+      Value::Null => res.push('\n'),
+      _ => res.push_str(&format!(" {}\n", render_value(value))),
+    }
+  }
+  res.push_str("---\n");
+  res
+}
+
+fn render_value(value: &Value) -> String {
+  match value {
+    Value::String(text)
+      if scalar(text) == Some(value.clone())
+        && text.trim() == text
+        && !text.contains(['\n', '\r']) =>
+    {
+      text.clone()
+    }
+    _ => value.to_string(),
+  }
+}
@@ -1,3 +1,9 @@
+//! Lua patterns, compiled at build time.
+//!
+//! The tokenizers are a straightforward port of djot.lua and use the same
+//! patterns. Only the subset of Lua pattern syntax we actually need is
+//! supported (no `%b`, `%f` or back references); `Pattern::new` panics, and
+//! so fails the build, on anything else.
 use std::ops::Range;
 
 #[derive(Debug, Default)]
@@ -35,25 +41,410 @@ impl PatMatch {
   }
 }
 
-pub fn find(subject: &str, pat: &'static str) -> PatMatch {
+/// `pat!("^%S")` is a `&'static Pattern`, compiled at build time.
+macro_rules! _pat {
+  ($pat:literal) => {{
+    const PAT: $crate::patterns::Pattern = $crate::patterns::Pattern::new($pat);
+    &PAT
+  }};
+}
+pub(crate) use _pat as pat;
+
+pub fn find(subject: &str, pat: &Pattern) -> PatMatch {
   find_at(subject, pat, 0)
 }
 
-pub fn find_at(subject: &str, pat: &'static str, start: usize) -> PatMatch {
-  let mut pat = lua_patterns::LuaPattern::new(pat);
-  let is_match = pat.matches(&subject[start..]);
-  let range = pat.range();
-  let cap1 = pat.capture(1);
-  let cap2 = pat.capture(2);
+pub fn find_at(subject: &str, pat: &Pattern, start: usize) -> PatMatch {
+  // Like Lua, match bytes: `start` needn't be on a char boundary.
+  let Some(bytes) = subject.as_bytes().get(start..) else { return PatMatch::default() };
+  let mut caps = [0..0, 0..0];
+  let range = pat.find(bytes, &mut caps);
+  let shift = |range: &Range<usize>| range.start + start..range.end + start;
   PatMatch {
-    start: range.start + start,
-    end: range.end + start,
-    is_match,
-    cap1: cap1.start + start..cap1.end + start,
-    cap2: cap2.start + start..cap2.end + start,
+    is_match: range.is_some(),
+    start: range.as_ref().map_or(0, |it| it.start) + start,
+    end: range.as_ref().map_or(0, |it| it.end) + start,
+    cap1: shift(&caps[0]),
+    cap2: shift(&caps[1]),
   }
 }
 
 pub(crate) fn is_space(c: char) -> bool {
   " \n\t".contains(c)
 }
+
+const MAX_ITEMS: usize = 16;
+const MAX_CAPTURES: usize = 2;
+
+pub struct Pattern {
+  anchored: bool,
+  items: [Item; MAX_ITEMS],
+  len: usize,
+  /// Bytes a match can start with, if it can't be empty. Lets unanchored
+  /// search skip ahead without trying to match at every position.
+  first: Option<ByteSet>,
+}
+
+#[derive(Clone, Copy)]
+enum Item {
+  Class(ByteSet, Rep),
+  Open(usize),
+  Close(usize),
+  /// `$` at the end of the pattern.
+  End,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Rep {
+  One,
+  /// `?`
+  Opt,
+  /// `*`
+  Star,
+  /// `+`
+  Plus,
+  /// `-`
+  Lazy,
+}
+
+#[derive(Clone, Copy)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+  const EMPTY: ByteSet = ByteSet([0; 4]);
+
+  const fn with(mut self, b: u8) -> ByteSet {
+    self.0[(b / 64) as usize] |= 1 << (b % 64);
+    self
+  }
+
+  const fn with_range(mut self, lo: u8, hi: u8) -> ByteSet {
+    let mut b = lo as usize;
+    while b <= hi as usize {
+      self = self.with(b as u8);
+      b += 1;
+    }
+    self
+  }
+
+  const fn union(mut self, other: ByteSet) -> ByteSet {
+    let mut i = 0;
+    while i < 4 {
+      self.0[i] |= other.0[i];
+      i += 1;
+    }
+    self
+  }
+
+  const fn not(mut self) -> ByteSet {
+    let mut i = 0;
+    while i < 4 {
+      self.0[i] = !self.0[i];
+      i += 1;
+    }
+    self
+  }
+
+  const fn contains(&self, b: u8) -> bool {
+    self.0[(b / 64) as usize] & (1 << (b % 64)) != 0
+  }
+
+  /// `%a`, `%S` and friends, ASCII only like Lua in the C locale (but `%s`
+  /// doesn't include `\v`, as in `lua-patterns`). Other escaped characters
+  /// stand for themselves.
+  const fn class(cl: u8) -> ByteSet {
+    let mut res = ByteSet::EMPTY;
+    let mut b = 0;
+    while b < 256 {
+      let c = b as u8;
+      let yes = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace(),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return ByteSet::EMPTY.with(cl),
+      };
+      if yes {
+        res = res.with(c);
+      }
+      b += 1;
+    }
+    if cl.is_ascii_uppercase() {
+      res = res.not();
+    }
+    res
+  }
+}
+
+impl Pattern {
+  pub const fn new(pat: &str) -> Pattern {
+    let p = pat.as_bytes();
+    let mut res = Pattern { anchored: false, items: [Item::End; MAX_ITEMS], len: 0, first: None };
+    let mut i = 0;
+    if i < p.len() && p[i] == b'^' {
+      res.anchored = true;
+      i += 1;
+    }
+    let mut captures = 0;
+    let mut open = [0; MAX_CAPTURES];
+    let mut depth = 0;
+    while i < p.len() {
+      assert!(res.len < MAX_ITEMS, "pattern too long");
+      let item = match p[i] {
+        b'(' => {
+          assert!(captures < MAX_CAPTURES, "too many captures");
+          open[depth] = captures;
+          depth += 1;
+          captures += 1;
+          i += 1;
+          Item::Open(captures - 1)
+        }
+        b')' => {
+          assert!(depth > 0, "invalid pattern capture");
+          depth -= 1;
+          i += 1;
+          Item::Close(open[depth])
+        }
+        b'$' if i + 1 == p.len() => {
+          i += 1;
+          Item::End
+        }
+        _ => {
+          let (set, next) = single(p, i);
+          i = next;
+          let rep = if i < p.len() {
+            match p[i] {
+              b'?' => Rep::Opt,
+              b'*' => Rep::Star,
+              b'+' => Rep::Plus,
+              b'-' => Rep::Lazy,
+              _ => Rep::One,
+            }
+          } else {
+            Rep::One
+          };
+          if !matches!(rep, Rep::One) {
+            i += 1;
+          }
+          Item::Class(set, rep)
+        }
+      };
+      res.items[res.len] = item;
+      res.len += 1;
+    }
+    assert!(depth == 0, "unfinished capture");
+    res.first = first_set(&res.items, res.len);
+    res
+  }
+
+  fn find(&self, s: &[u8], caps: &mut [Range<usize>; MAX_CAPTURES]) -> Option<Range<usize>> {
+    let mut start = 0;
+    loop {
+      if let (Some(first), false) = (&self.first, self.anchored) {
+        while start < s.len() && !first.contains(s[start]) {
+          start += 1;
+        }
+        if start == s.len() {
+          *caps = [0..0, 0..0];
+          return None;
+        }
+      }
+      if let Some(end) = self.do_match(s, start, 0, caps) {
+        return Some(start..end);
+      }
+      // Like Lua, don't try to match an empty suffix of a non-empty subject.
+      start += 1;
+      if self.anchored || start >= s.len() {
+        *caps = [0..0, 0..0];
+        return None;
+      }
+    }
+  }
+
+  fn do_match(
+    &self,
+    s: &[u8],
+    pos: usize,
+    idx: usize,
+    caps: &mut [Range<usize>; MAX_CAPTURES],
+  ) -> Option<usize> {
+    if idx == self.len {
+      return Some(pos);
+    }
+    let at = |pos: usize, set: &ByteSet| pos < s.len() && set.contains(s[pos]);
+    match &self.items[idx] {
+      Item::Open(cap) => {
+        caps[*cap].start = pos;
+        self.do_match(s, pos, idx + 1, caps)
+      }
+      Item::Close(cap) => {
+        caps[*cap].end = pos;
+        self.do_match(s, pos, idx + 1, caps)
+      }
+      Item::End => (pos == s.len()).then_some(pos),
+      Item::Class(set, rep) => match rep {
+        Rep::One => {
+          if !at(pos, set) {
+            return None;
+          }
+          self.do_match(s, pos + 1, idx + 1, caps)
+        }
+        Rep::Opt => {
+          if at(pos, set) {
+            if let Some(end) = self.do_match(s, pos + 1, idx + 1, caps) {
+              return Some(end);
+            }
+          }
+          self.do_match(s, pos, idx + 1, caps)
+        }
+        Rep::Star | Rep::Plus => {
+          let mut n = 0;
+          while at(pos + n, set) {
+            n += 1;
+          }
+          let min = if *rep == Rep::Plus { 1 } else { 0 };
+          while n >= min {
+            if let Some(end) = self.do_match(s, pos + n, idx + 1, caps) {
+              return Some(end);
+            }
+            if n == 0 {
+              break;
+            }
+            n -= 1;
+          }
+          None
+        }
+        Rep::Lazy => {
+          let mut n = 0;
+          loop {
+            if let Some(end) = self.do_match(s, pos + n, idx + 1, caps) {
+              return Some(end);
+            }
+            if !at(pos + n, set) {
+              return None;
+            }
+            n += 1;
+          }
+        }
+      },
+    }
+  }
+}
+
+/// Parses a single character class starting at `i`: `x`, `.`, `%a` or `[...]`.
+const fn single(p: &[u8], mut i: usize) -> (ByteSet, usize) {
+  match p[i] {
+    b'.' => (ByteSet::EMPTY.not(), i + 1),
+    b'%' => {
+      assert!(i + 1 < p.len(), "malformed pattern (ends with '%')");
+      (ByteSet::class(p[i + 1]), i + 2)
+    }
+    b'[' => {
+      i += 1;
+      let negate = i < p.len() && p[i] == b'^';
+      if negate {
+        i += 1;
+      }
+      let mut set = ByteSet::EMPTY;
+      // The first character is part of the set, even if it's `]`.
+      let mut first = true;
+      loop {
+        assert!(i < p.len(), "malformed pattern (missing ']')");
+        let c = p[i];
+        if c == b']' && !first {
+          i += 1;
+          break;
+        }
+        first = false;
+        if c == b'%' {
+          assert!(i + 1 < p.len(), "malformed pattern (ends with '%')");
+          set = set.union(ByteSet::class(p[i + 1]));
+          i += 2;
+        } else if i + 2 < p.len() && p[i + 1] == b'-' && p[i + 2] != b']' {
+          set = set.with_range(c, p[i + 2]);
+          i += 3;
+        } else {
+          set = set.with(c);
+          i += 1;
+        }
+      }
+      (if negate { set.not() } else { set }, i)
+    }
+    c => (ByteSet::EMPTY.with(c), i + 1),
+  }
+}
+
+const fn first_set(items: &[Item; MAX_ITEMS], len: usize) -> Option<ByteSet> {
+  let mut res = ByteSet::EMPTY;
+  let mut i = 0;
+  while i < len {
+    match items[i] {
+      Item::Class(set, Rep::One | Rep::Plus) => return Some(res.union(set)),
+      Item::Class(set, _) => res = res.union(set),
+      Item::Open(_) | Item::Close(_) => (),
+      Item::End => return None,
+    }
+    i += 1;
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+
+  /// Checks all patterns used by the tokenizers against `lua-patterns` at
+  /// every position of the test inputs.
+  #[test]
+  fn same_as_lua() {
+    let mut patterns = Vec::new();
+    for entry in fs::read_dir("./src").unwrap() {
+      let path = entry.unwrap().path();
+      if path.extension().unwrap_or_default() != "rs" {
+        continue;
+      }
+      let text = fs::read_to_string(path).unwrap();
+      for chunk in text.split("pat!(\"").skip(1) {
+        let pat = chunk.split("\")").next().unwrap();
+        let pat = pat.replace("\\t", "\t").replace("\\r", "\r").replace("\\n", "\n");
+        patterns.push(pat.replace("\\\"", "\"").replace("\\\\", "\\"));
+      }
+    }
+    assert!(patterns.len() > 30);
+
+    let mut subjects =
+      vec!["".to_string(), "\r\n".to_string(), "a\u{a0}b\x0b{x}[^]] `".to_string()];
+    for entry in fs::read_dir("./tests/data").unwrap() {
+      let path = entry.unwrap().path();
+      if path.extension().unwrap_or_default() == "test" {
+        subjects.push(fs::read_to_string(path).unwrap());
+      }
+    }
+
+    for pat in &patterns {
+      let compiled = Pattern::new(pat);
+      let mut lua = lua_patterns::LuaPattern::new(pat);
+      for subject in &subjects {
+        for start in 0..=subject.len() {
+          let bytes = &subject.as_bytes()[start..];
+          let want =
+            lua.matches_bytes(bytes).then(|| (lua.range(), lua.capture(1), lua.capture(2)));
+          let mut caps = [0..0, 0..0];
+          let got = compiled.find(bytes, &mut caps).map(|range| {
+            let [cap1, cap2] = caps.clone();
+            (range, cap1, cap2)
+          });
+          assert_eq!(got, want, "{pat:?} at {start} of {:?}", subject.get(..40).unwrap_or(subject));
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,245 @@
+//! Re-parsing after an edit, see [`Document::reparse`].
+//!
+//! The block tokenizer records the lines where no container is open. The
+//! input on either side of such a line parses independently, so parsing
+//! resumes at the last one before the edit and stops at the first one after
+//! it, once it reaches the same state as the previous parse.
+use std::{borrow::Cow, collections::HashSet, fmt, ops::Range, sync::Arc};
+
+use crate::{
+  ast::{self, borrowed, SourceSpan, Tag, VisitorMut},
+  block,
+  diagnostics::{Finding, Warning},
+  text,
+  tree::{self, Block},
+  Document, ParseError, ParseOpts,
+};
+
+/// What [`Document::reparse`] reuses from the previous parse.
+#[derive(Default, Clone)]
+pub(crate) struct State {
+  opts: ParseOpts,
+  /// The parsed text, plus a final newline if it lacked one.
+  subject: Arc<str>,
+  len: usize,
+  /// End of the front matter, see [`block::Tokenizer::front_matter`].
+  front_matter: usize,
+  blocks: Vec<Block>,
+  definitions: Vec<Definition>,
+  /// Sorted by start offset.
+  findings: Vec<Finding>,
+}
+
+impl fmt::Debug for State {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("State").finish_non_exhaustive()
+  }
+}
+
+impl State {
+  /// The parsed text, plus a final newline if it lacked one.
+  pub(crate) fn subject(&self) -> &str {
+    &self.subject
+  }
+
+  pub(crate) fn findings(&self) -> &[Finding] {
+    &self.findings
+  }
+}
+
+type Definition = (usize, String, ast::ReferenceDefinition);
+
+pub(crate) fn parse(opts: ParseOpts, text: &str) -> (Document, Option<ParseError>) {
+  let mut p = block::Tokenizer::new(text, opts.clone());
+  p.parse();
+  let subject = Arc::clone(&p.subject);
+  let front_matter = p.front_matter;
+  let warnings = std::mem::take(&mut p.warnings);
+  let tree = tree::build(p, text);
+  let mut doc = tree.doc.into_owned();
+  let definitions = into_owned(tree.definitions);
+  let blocks = tree.blocks;
+  let findings = merge(tree.findings, warnings);
+  doc.state = State { opts, subject, len: text.len(), front_matter, blocks, definitions, findings };
+  (doc, tree.error)
+}
+
+pub(crate) fn reparse(doc: &mut Document, edit: Range<usize>, new_text: &str) {
+  let state = std::mem::take(&mut doc.state);
+  let mut text = state.subject[..state.len].to_string();
+  text.replace_range(edit.clone(), new_text);
+  let opts = state.opts.clone();
+  if opts.debug_matches || opts.max_input_bytes != usize::MAX || opts.max_matches != usize::MAX {
+    // These depend on the input as a whole.
+    *doc = parse(opts, &text).0;
+    return;
+  }
+  // Without front matter, any line could close an opening `---`.
+  if edit.start < state.front_matter || (state.front_matter == 0 && text.starts_with("---")) {
+    *doc = parse(opts, &text).0;
+    return;
+  }
+
+  let delta = new_text.len() as isize - edit.len() as isize;
+  let shift = |offset: usize| offset.wrapping_add_signed(delta);
+
+  let first = state.blocks.partition_point(|it| it.start <= edit.start).saturating_sub(1);
+  let Block { start, child } = state.blocks.get(first).copied().unwrap_or_default();
+  let mut p = block::Tokenizer::new(&text, opts.clone());
+  p.pos = start;
+  p.resync = state.blocks[first..]
+    .iter()
+    .filter(|it| it.start >= edit.end)
+    .map(|it| shift(it.start))
+    .collect();
+  p.parse();
+  let resynced = (p.pos < p.subject.len()).then(|| p.pos.wrapping_add_signed(-delta));
+  let subject = Arc::clone(&p.subject);
+  let warnings = std::mem::take(&mut p.warnings);
+  let tree = tree::build(p, &subject);
+  // Blocks index the children before they are grouped in sections.
+  doc.children = unsection(std::mem::take(&mut doc.children));
+
+  // Parsing stopped at the block starting at `end`, the blocks from there on
+  // are the same as before, only further along.
+  let end = resynced.unwrap_or(usize::MAX);
+  let last = state.blocks.partition_point(|it| it.start < end);
+  let resume = state.blocks.get(last).map_or(doc.children.len(), |it| it.child);
+  let mut suffix = doc.children.split_off(resume);
+  let mut suffix_definitions: Vec<Definition> =
+    state.definitions.iter().filter(|it| it.0 >= end).cloned().collect();
+  if opts.source_positions {
+    let lines =
+      new_text.matches('\n').count() as isize - state.subject[edit].matches('\n').count() as isize;
+    let mut shift = Shift { offset: delta, lines };
+    shift.visit_children_mut(&mut suffix);
+    for (_, _, def) in &mut suffix_definitions {
+      shift.span(&mut def.pos);
+    }
+  }
+
+  let middle = unsection(tree.doc.children.into_iter().map(borrowed::Tag::into_owned));
+  let middle_children = middle.len();
+  doc.children.truncate(child);
+  doc.children.extend(middle);
+  doc.children.extend(suffix);
+  let implicit_references;
+  (doc.children, implicit_references) = tree::sections(std::mem::take(&mut doc.children));
+
+  let mut blocks = state.blocks[..first].to_vec();
+  blocks.extend(tree.blocks.iter().map(|it| Block { start: it.start, child: child + it.child }));
+  blocks.extend(state.blocks[last..].iter().map(|it| Block {
+    start: shift(it.start),
+    child: it.child - resume + child + middle_children,
+  }));
+
+  let mut definitions: Vec<Definition> =
+    state.definitions.into_iter().filter(|it| it.0 < start).collect();
+  definitions.extend(into_owned(tree.definitions));
+  definitions.extend(suffix_definitions.into_iter().map(|(offset, k, v)| (shift(offset), k, v)));
+  doc.references = definitions.iter().map(|(_, k, v)| (k.clone(), v.clone())).collect();
+  for (key, destination) in implicit_references {
+    doc.references.entry(key).or_insert_with(|| ast::ReferenceDefinition {
+      destination,
+      ..ast::ReferenceDefinition::default()
+    });
+  }
+
+  let mut findings: Vec<Finding> =
+    state.findings.iter().filter(|it| it.range.start < start).cloned().collect();
+  findings.extend(merge(tree.findings, warnings));
+  findings.extend(state.findings.into_iter().filter(|it| it.range.start >= end).map(|mut it| {
+    it.range = shift(it.range.start)..shift(it.range.end);
+    it
+  }));
+
+  let front_matter = state.front_matter;
+  let len = shift(state.len);
+  doc.state = State { opts, subject, len, front_matter, blocks, definitions, findings };
+}
+
+/// The findings of the tree and the tokenizer warnings, in source order.
+fn merge(mut findings: Vec<Finding>, warnings: Vec<Warning>) -> Vec<Finding> {
+  findings.extend(warnings.into_iter().map(Finding::from));
+  findings.sort_by_key(|it| it.range.start);
+  findings
+}
+
+/// The top-level blocks the sections group. Headings get back the section id
+/// if it isn't the one [`tree::sections`] would generate for them.
+fn unsection(children: impl IntoIterator<Item = Tag>) -> Vec<Tag> {
+  fn go(
+    children: impl IntoIterator<Item = Tag>,
+    identifiers: &mut HashSet<String>,
+    res: &mut Vec<Tag>,
+  ) {
+    for child in children {
+      let Tag::Section(mut section) = child else {
+        res.push(child);
+        continue;
+      };
+      if let (Some(Tag::Heading(heading)), Some(id)) =
+        (section.children.first_mut(), section.attrs.get("id"))
+      {
+        let text = text::inline_text(&heading.children);
+        if tree::get_identifier(identifiers, text.trim()) != *id {
+          heading.attrs.insert("id".to_string(), id.clone());
+        }
+        identifiers.insert(id.clone());
+      }
+      go(section.children, identifiers, res)
+    }
+  }
+  let mut res = Vec::new();
+  go(children, &mut HashSet::new(), &mut res);
+  res
+}
+
+fn into_owned(
+  definitions: Vec<(usize, Cow<str>, borrowed::ReferenceDefinition)>,
+) -> Vec<Definition> {
+  definitions.into_iter().map(|(offset, k, v)| (offset, k.into_owned(), v.into_owned())).collect()
+}
+
+/// Moves source positions after the edit to their new place. Reused blocks
+/// start on a line of their own, so columns stay the same.
+struct Shift {
+  offset: isize,
+  lines: isize,
+}
+
+impl Shift {
+  fn span(&self, span: &mut Option<SourceSpan>) {
+    if let Some(span) = span {
+      for pos in [&mut span.start, &mut span.end] {
+        pos.offset = pos.offset.wrapping_add_signed(self.offset);
+        pos.line = pos.line.wrapping_add_signed(self.lines);
+      }
+    }
+  }
+}
+
+impl VisitorMut for Shift {
+  fn visit_children_mut(&mut self, children: &mut [Tag]) {
+    for child in children {
+      let mut pos = child.pos();
+      self.span(&mut pos);
+      child.set_pos(pos);
+      self.visit_tag_mut(child)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::Document;
+
+  #[test]
+  fn explicit_heading_ids() {
+    // The ids live on the sections while the blocks are spliced.
+    let text = "# Head\n\npara\n\n{#x}\n## Sub\n\nlast\n";
+    let mut doc = Document::parse(text);
+    doc.reparse(0..0, "");
+    assert_eq!(doc.to_json(), Document::parse(text).to_json());
+  }
+}
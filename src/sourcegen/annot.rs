@@ -10,6 +10,7 @@ url
 subscript
 superscript
 para
+heading
 code_block
 imagetext
 linktext
@@ -24,6 +25,7 @@ insert
 delete
 mark
 attributes
+block_attributes
 
 str
 escape
@@ -68,7 +70,7 @@ fn emit_comp(buf: &mut String, composites: &str) {
     buf,
     "\
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) enum Comp {{
+pub enum Comp {{
 "
   );
   for ident in composites.lines() {
@@ -105,7 +107,7 @@ fn emit_atom(buf: &mut String, atoms: &str) {
     buf,
     "
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) enum Atom {{
+pub enum Atom {{
   {variants}
 }}
 "
@@ -3,6 +3,7 @@ use crate::{format_to, sourcegen::camel_case};
 use crate::sourcegen::ensure_content;
 
 const TAGS: &str = "
+section
 heading level: u32
 para
 link destination: Option<String>, reference: Option<String>
@@ -31,11 +32,15 @@ emoji alias: String
 fn generate_annotations() {
   let (composites, atoms) = TAGS.trim().split_once("\n\n").unwrap();
 
-  let mut buf = format!("use super::Attrs;\n");
+  let mut buf = format!("use super::{{Attrs, SourceSpan}};\n");
   emit_ast_comp(&mut buf, composites);
   emit_ast_atom(&mut buf, atoms);
   emit_ast_tag(&mut buf, composites, atoms);
+  emit_visitor(&mut buf, composites, atoms);
+  emit_visitor_mut(&mut buf, composites, atoms);
+  emit_fold(&mut buf, composites, atoms);
   ensure_content("src/ast/generated.rs", &buf);
+  ensure_content("src/ast/borrowed/generated.rs", &emit_borrowed(composites, atoms));
 }
 
 fn emit_ast_comp(buf: &mut String, composites: &str) {
@@ -48,10 +53,12 @@ fn emit_ast_comp(buf: &mut String, composites: &str) {
     };
 
     format_to! {buf, "
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct {} {{
-  #[serde(skip_serializing_if = \"Attrs::is_empty\")]
+  #[serde(default, skip_serializing_if = \"Attrs::is_empty\")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = \"Option::is_none\")]
+  pub pos: Option<SourceSpan>,
   pub children: Vec<Tag>,
   {fields}
 }}
@@ -68,10 +75,12 @@ fn emit_ast_atom(buf: &mut String, atoms: &str) {
       fields.split(", ").map(|it| format!("pub {it},\n")).collect::<String>()
     };
     format_to! {buf, "
-#[derive(Debug, Default, Clone, serde::Serialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct {} {{
-  #[serde(skip_serializing_if = \"Attrs::is_empty\")]
+  #[serde(default, skip_serializing_if = \"Attrs::is_empty\")]
   pub attrs: Attrs,
+  #[serde(default, skip_serializing_if = \"Option::is_none\")]
+  pub pos: Option<SourceSpan>,
   {fields}
 }}
 ", camel_case(ident)}
@@ -90,12 +99,247 @@ fn emit_ast_tag(buf: &mut String, composites: &str, atoms: &str) {
     let camel = camel_case(ident);
     format_to!(variants, "  {camel}({camel}),\n");
   }
+  let mut pos_arms = String::new();
+  let mut set_pos_arms = String::new();
+  let mut attrs_arms = String::new();
+  for tag in composites.lines().chain(atoms.lines()) {
+    let ident = tag.split_once(" ").map_or(tag, |it| it.0);
+    let camel = camel_case(ident);
+    format_to!(pos_arms, "      Tag::{camel}(it) => it.pos,\n");
+    format_to!(set_pos_arms, "      Tag::{camel}(it) => it.pos = pos,\n");
+    format_to!(attrs_arms, "      Tag::{camel}(it) => &mut it.attrs,\n");
+  }
   format_to!(
     buf,
     "
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(tag = \"tag\", rename_all = \"snake_case\")]
 pub enum Tag {{ {variants} }}
+
+impl Tag {{
+  pub fn pos(&self) -> Option<SourceSpan> {{
+    match self {{
+      {pos_arms}
+    }}
+  }}
+  pub fn set_pos(&mut self, pos: Option<SourceSpan>) {{
+    match self {{
+      {set_pos_arms}
+    }}
+  }}
+  pub fn attrs_mut(&mut self) -> &mut Attrs {{
+    match self {{
+      {attrs_arms}
+    }}
+  }}
+}}
+"
+  )
+}
+
+fn idents(tags: &str) -> impl Iterator<Item = &str> {
+  tags.lines().map(|tag| tag.split_once(" ").map_or(tag, |it| it.0))
+}
+
+fn emit_visitor(buf: &mut String, composites: &str, atoms: &str) {
+  let mut arms = String::new();
+  let mut methods = String::new();
+  for ident in idents(composites) {
+    let camel = camel_case(ident);
+    format_to!(arms, "      Tag::{camel}(it) => self.visit_{ident}(it),\n");
+    format_to!(
+      methods,
+      "  fn visit_{ident}(&mut self, it: &{camel}) {{ self.visit_children(&it.children) }}\n"
+    );
+  }
+  for ident in idents(atoms) {
+    let camel = camel_case(ident);
+    format_to!(arms, "      Tag::{camel}(it) => self.visit_{ident}(it),\n");
+    format_to!(methods, "  fn visit_{ident}(&mut self, _it: &{camel}) {{}}\n");
+  }
+  format_to!(
+    buf,
+    "
+pub trait Visitor {{
+  fn visit_tag(&mut self, tag: &Tag) {{
+    match tag {{
+      {arms}
+    }}
+  }}
+  fn visit_children(&mut self, children: &[Tag]) {{
+    for child in children {{
+      self.visit_tag(child)
+    }}
+  }}
+{methods}
+}}
+"
+  )
+}
+
+fn emit_visitor_mut(buf: &mut String, composites: &str, atoms: &str) {
+  let mut arms = String::new();
+  let mut methods = String::new();
+  for ident in idents(composites) {
+    let camel = camel_case(ident);
+    format_to!(arms, "      Tag::{camel}(it) => self.visit_{ident}_mut(it),\n");
+    format_to!(
+      methods,
+      "  fn visit_{ident}_mut(&mut self, it: &mut {camel}) {{ self.visit_children_mut(&mut it.children) }}\n"
+    );
+  }
+  for ident in idents(atoms) {
+    let camel = camel_case(ident);
+    format_to!(arms, "      Tag::{camel}(it) => self.visit_{ident}_mut(it),\n");
+    format_to!(methods, "  fn visit_{ident}_mut(&mut self, _it: &mut {camel}) {{}}\n");
+  }
+  format_to!(
+    buf,
+    "
+pub trait VisitorMut {{
+  fn visit_tag_mut(&mut self, tag: &mut Tag) {{
+    match tag {{
+      {arms}
+    }}
+  }}
+  fn visit_children_mut(&mut self, children: &mut [Tag]) {{
+    for child in children {{
+      self.visit_tag_mut(child)
+    }}
+  }}
+{methods}
+}}
 "
   )
 }
+
+fn emit_fold(buf: &mut String, composites: &str, atoms: &str) {
+  let mut arms = String::new();
+  let mut methods = String::new();
+  for ident in idents(composites) {
+    let camel = camel_case(ident);
+    format_to!(arms, "      Tag::{camel}(it) => self.fold_{ident}(it),\n");
+    format_to!(
+      methods,
+      "
+  fn fold_{ident}(&mut self, mut it: {camel}) -> Tag {{
+    it.children = self.fold_children(std::mem::take(&mut it.children));
+    Tag::{camel}(it)
+  }}"
+    );
+  }
+  for ident in idents(atoms) {
+    let camel = camel_case(ident);
+    format_to!(arms, "      Tag::{camel}(it) => self.fold_{ident}(it),\n");
+    format_to!(
+      methods,
+      "  fn fold_{ident}(&mut self, it: {camel}) -> Tag {{ Tag::{camel}(it) }}\n"
+    );
+  }
+  format_to!(
+    buf,
+    "
+pub trait Fold {{
+  fn fold_tag(&mut self, tag: Tag) -> Tag {{
+    match tag {{
+      {arms}
+    }}
+  }}
+  fn fold_children(&mut self, children: Vec<Tag>) -> Vec<Tag> {{
+    children.into_iter().map(|it| self.fold_tag(it)).collect()
+  }}
+{methods}
+}}
+"
+  )
+}
+
+fn emit_borrowed(composites: &str, atoms: &str) -> String {
+  let mut buf = "\
+use std::borrow::Cow;
+
+use super::{attrs_into_owned, Attrs};
+use crate::ast::{self, SourceSpan};
+"
+  .to_string();
+  let tags = composites.lines().map(|it| (it, true)).chain(atoms.lines().map(|it| (it, false)));
+  let mut variants = String::new();
+  let mut into_owned_arms = String::new();
+  let mut pos_arms = String::new();
+  let mut set_pos_arms = String::new();
+  let mut attrs_arms = String::new();
+  for (tag, is_comp) in tags {
+    let (ident, fields) = tag.split_once(" ").unwrap_or((tag, ""));
+    let camel = camel_case(ident);
+    let mut decls = String::new();
+    let mut conv = String::new();
+    if is_comp {
+      format_to!(decls, "pub children: Vec<Tag<'s>>,\n");
+      format_to!(conv, "children: self.children.into_iter().map(Tag::into_owned).collect(),\n");
+    }
+    for field in fields.split(", ").filter(|it| !it.is_empty()) {
+      let (name, ty) = field.split_once(": ").unwrap();
+      let (ty, expr) = match ty {
+        "String" => ("Cow<'s, str>", format!("self.{name}.into_owned()")),
+        "Option<String>" => ("Option<Cow<'s, str>>", format!("self.{name}.map(Cow::into_owned)")),
+        _ => (ty, format!("self.{name}")),
+      };
+      format_to!(decls, "pub {name}: {ty},\n");
+      format_to!(conv, "{name}: {expr},\n");
+    }
+    format_to! {buf, "
+#[derive(Debug, Default, Clone)]
+pub struct {camel}<'s> {{
+  pub attrs: Attrs<'s>,
+  pub pos: Option<SourceSpan>,
+  {decls}
+}}
+
+impl<'s> {camel}<'s> {{
+  pub fn into_owned(self) -> ast::{camel} {{
+    ast::{camel} {{
+      attrs: attrs_into_owned(self.attrs),
+      pos: self.pos,
+      {conv}
+    }}
+  }}
+}}
+"}
+    format_to!(variants, "  {camel}({camel}<'s>),\n");
+    format_to!(into_owned_arms, "      Tag::{camel}(it) => ast::Tag::{camel}(it.into_owned()),\n");
+    format_to!(pos_arms, "      Tag::{camel}(it) => it.pos,\n");
+    format_to!(set_pos_arms, "      Tag::{camel}(it) => it.pos = pos,\n");
+    format_to!(attrs_arms, "      Tag::{camel}(it) => &mut it.attrs,\n");
+  }
+  format_to!(
+    buf,
+    "
+#[derive(Debug, Clone)]
+pub enum Tag<'s> {{ {variants} }}
+
+impl<'s> Tag<'s> {{
+  pub fn into_owned(self) -> ast::Tag {{
+    match self {{
+      {into_owned_arms}
+    }}
+  }}
+  pub fn pos(&self) -> Option<SourceSpan> {{
+    match self {{
+      {pos_arms}
+    }}
+  }}
+  pub fn set_pos(&mut self, pos: Option<SourceSpan>) {{
+    match self {{
+      {set_pos_arms}
+    }}
+  }}
+  pub fn attrs_mut(&mut self) -> &mut Attrs<'s> {{
+    match self {{
+      {attrs_arms}
+    }}
+  }}
+}}
+"
+  );
+  buf
+}
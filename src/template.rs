@@ -0,0 +1,195 @@
+//! Templates for standalone HTML pages, see [`HtmlOpts::standalone`].
+//!
+//! `$name$` is replaced with the value of a variable, `$$` is a dollar.
+//! `$if(name)$ … $else$ … $endif$` checks if a variable is set and not empty,
+//! `$for(name)$ … $endfor$` repeats for each value of a variable, with `$name$`
+//! standing for the current one. A line holding just a `$if$`, `$else$`,
+//! `$endif$`, `$for$` or `$endfor$` is dropped from the output.
+use std::{collections::BTreeMap, fmt};
+
+#[cfg(doc)]
+use crate::HtmlOpts;
+
+/// The template used if none is given.
+pub const DEFAULT_TEMPLATE: &str = include_str!("./template.html");
+
+/// Variables of a template, a variable can have several values.
+pub type Vars = BTreeMap<String, Vec<String>>;
+
+#[derive(Debug, Clone)]
+pub struct Template {
+  nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+  Text(String),
+  Var(String),
+  If(String, Vec<Node>, Vec<Node>),
+  For(String, Vec<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+  /// Byte offset into the template.
+  pub offset: usize,
+  pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid template at byte {}: {}", self.offset, self.message)
+  }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl Default for Template {
+  fn default() -> Template {
+    Template::parse(DEFAULT_TEMPLATE).unwrap()
+  }
+}
+
+impl Template {
+  pub fn parse(text: &str) -> Result<Template, TemplateError> {
+    let mut p = Parser { text, pos: 0 };
+    let (nodes, end) = p.parse_nodes()?;
+    match end {
+      None => Ok(Template { nodes }),
+      Some(end) => Err(p.error(format!("unexpected ${end}$"))),
+    }
+  }
+
+  /// Values are inserted as is, escape them as needed.
+  pub fn render(&self, vars: &Vars) -> String {
+    let mut res = String::new();
+    render_nodes(&self.nodes, vars, &mut Vec::new(), &mut res);
+    res
+  }
+}
+
+fn render_nodes<'a>(
+  nodes: &'a [Node],
+  vars: &'a Vars,
+  locals: &mut Vec<(&'a str, &'a str)>,
+  res: &mut String,
+) {
+  let lookup = |locals: &Vec<(&str, &'a str)>, name: &str| -> Vec<&'a str> {
+    match locals.iter().rev().find(|it| it.0 == name) {
+      Some(&(_, value)) => vec![value],
+      None => vars.get(name).map_or(Vec::new(), |it| it.iter().map(String::as_str).collect()),
+    }
+  };
+  for node in nodes {
+    match node {
+      Node::Text(text) => res.push_str(text),
+      Node::Var(name) => res.push_str(&lookup(locals, name).concat()),
+      Node::If(name, then, else_) => {
+        let set = lookup(locals, name).iter().any(|it| !it.is_empty());
+        render_nodes(if set { then } else { else_ }, vars, locals, res)
+      }
+      Node::For(name, body) => {
+        for value in lookup(locals, name) {
+          locals.push((name, value));
+          render_nodes(body, vars, locals, res);
+          locals.pop();
+        }
+      }
+    }
+  }
+}
+
+struct Parser<'a> {
+  text: &'a str,
+  pos: usize,
+}
+
+impl<'a> Parser<'a> {
+  /// Parses up to the end of the input or an `else`, `endif` or `endfor`,
+  /// returning which one.
+  fn parse_nodes(&mut self) -> Result<(Vec<Node>, Option<&'a str>), TemplateError> {
+    let mut res = Vec::new();
+    let mut text = String::new();
+    loop {
+      let Some(idx) = self.text[self.pos..].find('$') else {
+        text.push_str(&self.text[self.pos..]);
+        self.pos = self.text.len();
+        break;
+      };
+      text.push_str(&self.text[self.pos..self.pos + idx]);
+      let start = self.pos + idx;
+      self.pos = start + 1;
+      if self.text[self.pos..].starts_with('$') {
+        text.push('$');
+        self.pos += 1;
+        continue;
+      }
+      let Some(len) = self.text[self.pos..].find('$') else {
+        return Err(self.error("unclosed `$`".to_string()));
+      };
+      let directive = &self.text[self.pos..self.pos + len];
+      self.pos += len + 1;
+
+      let arg = |name: &str| directive.strip_prefix(name)?.strip_prefix('(')?.strip_suffix(')');
+      if !matches!(directive, "else" | "endif" | "endfor")
+        && arg("if").is_none()
+        && arg("for").is_none()
+      {
+        if !is_name(directive) {
+          return Err(self.error(format!("bad variable name `{directive}`")));
+        }
+        text_node(&mut res, &mut text);
+        res.push(Node::Var(directive.to_string()));
+        continue;
+      }
+
+      // Drop the line if it has nothing else.
+      let line_start = self.text[..start].rfind('\n').map_or(0, |it| it + 1);
+      let line_end =
+        self.text[self.pos..].find('\n').map_or(self.text.len(), |it| self.pos + it + 1);
+      if self.text[line_start..start].trim().is_empty()
+        && self.text[self.pos..line_end].trim().is_empty()
+      {
+        text.truncate(text.trim_end_matches([' ', '\t']).len());
+        self.pos = line_end;
+      }
+      text_node(&mut res, &mut text);
+
+      if let Some(name) = arg("if") {
+        let (then, end) = self.parse_nodes()?;
+        let else_ = match end {
+          Some("else") => match self.parse_nodes()? {
+            (else_, Some("endif")) => else_,
+            _ => return Err(self.error("expected `$endif$`".to_string())),
+          },
+          Some("endif") => Vec::new(),
+          _ => return Err(self.error("expected `$endif$`".to_string())),
+        };
+        res.push(Node::If(name.to_string(), then, else_));
+      } else if let Some(name) = arg("for") {
+        match self.parse_nodes()? {
+          (body, Some("endfor")) => res.push(Node::For(name.to_string(), body)),
+          _ => return Err(self.error("expected `$endfor$`".to_string())),
+        }
+      } else {
+        return Ok((res, Some(directive)));
+      }
+    }
+    text_node(&mut res, &mut text);
+    Ok((res, None))
+  }
+
+  fn error(&self, message: String) -> TemplateError {
+    TemplateError { offset: self.pos, message }
+  }
+}
+
+fn text_node(nodes: &mut Vec<Node>, text: &mut String) {
+  if !text.is_empty() {
+    nodes.push(Node::Text(std::mem::take(text)))
+  }
+}
+
+fn is_name(name: &str) -> bool {
+  !name.is_empty() && name.chars().all(|it| it.is_alphanumeric() || matches!(it, '_' | '-' | '.'))
+}
@@ -0,0 +1,75 @@
+use crate::{ast::Tag, Document};
+
+/// The text of the document without any markup, blocks separated by blank
+/// lines.
+pub(crate) fn convert(doc: &Document) -> String {
+  let mut ctx = Ctx { res: String::new() };
+  ctx.render_blocks(&doc.children);
+  ctx.res
+}
+
+/// The text of inline content, like a heading. Also gives heading ids and
+/// `[text][]` reference keys, as `get_string_content` does in djot.lua.
+pub(crate) fn inline_text(children: &[Tag]) -> String {
+  let mut ctx = Ctx { res: String::new() };
+  ctx.render_children(children);
+  ctx.res
+}
+
+struct Ctx {
+  res: String,
+}
+
+impl Ctx {
+  fn render(&mut self, tag: &Tag) {
+    match tag {
+      Tag::Section(section) => self.render_blocks(&section.children),
+      Tag::Heading(heading) => self.render_children(&heading.children),
+      Tag::Para(para) => self.render_children(&para.children),
+      Tag::Link(link) => self.render_children(&link.children),
+      Tag::Image(image) => self.render_children(&image.children),
+      Tag::CodeBlock(code_block) => self.res.push_str(&code_block.text),
+      Tag::Strong(strong) => self.render_children(&strong.children),
+      Tag::Emph(emph) => self.render_children(&emph.children),
+      Tag::Insert(insert) => self.render_children(&insert.children),
+      Tag::Delete(delete) => self.render_children(&delete.children),
+      Tag::Mark(mark) => self.render_children(&mark.children),
+      Tag::Superscript(superscript) => self.render_children(&superscript.children),
+      Tag::Subscript(subscript) => self.render_children(&subscript.children),
+      Tag::Span(span) => self.render_children(&span.children),
+      Tag::DoubleQuoted(double_quoted) => {
+        self.res.push('“');
+        self.render_children(&double_quoted.children);
+        self.res.push('”');
+      }
+      Tag::Url(url) => self.res.push_str(&url.destination),
+      Tag::SoftBreak(_) => self.res.push('\n'),
+      Tag::EmDash(_) => self.res.push('—'),
+      Tag::EnDash(_) => self.res.push('–'),
+      Tag::Verbatim(verbatim) => self.res.push_str(&verbatim.text),
+      Tag::Str(str) => self.res.push_str(&str.text),
+      Tag::Emoji(emoji) => match crate::emoji::find_emoji(&emoji.alias) {
+        Some(emoji) => self.res.push_str(emoji),
+        None => self.res.push_str(&format!(":{}:", emoji.alias)),
+      },
+    }
+  }
+
+  fn render_blocks(&mut self, blocks: &[Tag]) {
+    for block in blocks {
+      if !self.res.is_empty() && !matches!(block, Tag::Section(_)) {
+        self.res.push('\n');
+      }
+      self.render(block);
+      if !self.res.ends_with('\n') {
+        self.res.push('\n');
+      }
+    }
+  }
+
+  fn render_children(&mut self, children: &[Tag]) {
+    for child in children {
+      self.render(child)
+    }
+  }
+}
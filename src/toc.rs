@@ -0,0 +1,18 @@
+use crate::{ast::Tag, text, TocEntry};
+
+pub(crate) fn toc(children: &[Tag]) -> Vec<TocEntry> {
+  let mut res = Vec::new();
+  for child in children {
+    let Tag::Section(section) = child else { continue };
+    match section.children.first() {
+      Some(Tag::Heading(heading)) => res.push(TocEntry {
+        level: heading.level,
+        id: section.attrs.get("id").cloned().unwrap_or_default(),
+        text: text::inline_text(&heading.children),
+        children: toc(&section.children),
+      }),
+      _ => res.extend(toc(&section.children)),
+    }
+  }
+  res
+}
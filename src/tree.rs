@@ -1,48 +1,147 @@
-use std::collections::BTreeMap;
+use std::{borrow::Cow, collections::HashSet, ops::Range, sync::Arc};
 
 use crate::{
   annot::{Annot, Atom, Comp},
   ast::{
-    Attrs, CodeBlock, Delete, DoubleQuoted, Emoji, Emph, Image, Insert, Link, Mark, Para,
-    ReferenceDefinition, SoftBreak, Span, Str, Strong, Subscript, Superscript, Tag, Url, Verbatim,
+    borrowed::{
+      Attrs, CodeBlock, Delete, Document, DoubleQuoted, EmDash, Emoji, Emph, EnDash, Heading,
+      Image, Insert, Link, Mark, Para, ReferenceDefinition, Section, SoftBreak, Span, Str, Strong,
+      Subscript, Superscript, Tag, Url, Verbatim,
+    },
+    normalize_label, SourcePos, SourceSpan,
   },
+  attribute::collect_attrs,
   block,
-  patterns::find,
-  Document, Match,
+  diagnostics::{Finding, Kind},
+  emoji,
+  patterns::{find, pat},
+  text, Match, ParseError, ParseOpts,
 };
 
-pub(crate) fn build(p: block::Tokenizer) -> Document {
-  let mut ctx = Ctx { subject: p.subject, matches: p.matches, idx: 0, references: BTreeMap::new() };
+pub(crate) struct Tree<'s> {
+  pub(crate) doc: Document<'s>,
+  /// The first violation of the match stream invariants, if any.
+  pub(crate) error: Option<ParseError>,
+  pub(crate) blocks: Vec<Block>,
+  /// Every reference definition with its start offset, in source order.
+  pub(crate) definitions: Vec<(usize, Cow<'s, str>, ReferenceDefinition<'s>)>,
+  /// What [`crate::diagnostics`] needs to know, in source order.
+  pub(crate) findings: Vec<Finding>,
+}
+
+/// A top-level block, see [`block::Tokenizer::block_starts`].
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Block {
+  pub(crate) start: usize,
+  /// Index of the first child of the document built from this block.
+  pub(crate) child: usize,
+}
+
+/// Builds the tree. The document is built regardless of errors, skipping bad
+/// matches.
+///
+/// `src` is the text `p` parsed, text in the tree borrows from it.
+pub(crate) fn build(p: block::Tokenizer, src: &str) -> Tree<'_> {
+  let line_starts = if p.opts.source_positions {
+    std::iter::once(0).chain(p.subject.match_indices('\n').map(|(idx, _)| idx + 1)).collect()
+  } else {
+    Vec::new()
+  };
+  let mut ctx = Ctx {
+    opts: p.opts,
+    src,
+    subject: p.subject,
+    matches: p.matches,
+    idx: 0,
+    depth: 0,
+    open: Vec::new(),
+    definitions: Vec::new(),
+    findings: Vec::new(),
+    block_attrs: Vec::new(),
+    block_starts: p.block_starts,
+    blocks: Vec::new(),
+    line_starts,
+    error: None,
+  };
   let mut doc = ctx.get_doc();
   doc.debug = p.debug;
-  doc.references = ctx.references;
-  doc
+  doc.metadata = p.metadata;
+  doc.references = ctx.definitions.iter().map(|(_, k, v)| (k.clone(), v.clone())).collect();
+  let implicit_references;
+  (doc.children, implicit_references) = sections(doc.children);
+  for (key, destination) in implicit_references {
+    doc.references.entry(Cow::Owned(key)).or_insert_with(|| ReferenceDefinition {
+      destination: Cow::Owned(destination),
+      ..ReferenceDefinition::default()
+    });
+  }
+  Tree {
+    doc,
+    error: ctx.error,
+    blocks: ctx.blocks,
+    definitions: ctx.definitions,
+    findings: ctx.findings,
+  }
 }
 
-struct Ctx {
-  subject: String,
+struct Ctx<'s> {
+  opts: ParseOpts,
+  src: &'s str,
+  /// `src`, plus a final newline if it lacks one.
+  subject: Arc<str>,
   matches: Vec<Match>,
-  references: BTreeMap<String, ReferenceDefinition>,
+  definitions: Vec<(usize, Cow<'s, str>, ReferenceDefinition<'s>)>,
+  findings: Vec<Finding>,
+  /// Matches of the block attributes for the next block.
+  block_attrs: Vec<Match>,
+  block_starts: Vec<usize>,
+  blocks: Vec<Block>,
   idx: usize,
+  depth: usize,
+  /// Containers whose children are being collected, innermost last.
+  open: Vec<Comp>,
+  line_starts: Vec<usize>,
+  error: Option<ParseError>,
 }
 
-impl Ctx {
-  fn get_doc(&mut self) -> Document {
+impl<'s> Ctx<'s> {
+  fn get_doc(&mut self) -> Document<'s> {
     let mut res = Document::default();
-    while self.idx < self.matches.len() {
+    let mut starts = std::mem::take(&mut self.block_starts).into_iter().peekable();
+    loop {
+      self.skip_trivia();
+      let Some(pos) = self.matches.get(self.idx).map(|it| it.range.start) else { break };
+      while let Some(start) = starts.next_if(|&it| it <= pos) {
+        self.blocks.push(Block { start, child: res.children.len() });
+      }
       self.get_tag(&mut res.children)
     }
+    self.blocks.extend(starts.map(|start| Block { start, child: res.children.len() }));
     res
   }
 
-  fn get_tag(&mut self, acc: &mut Vec<Tag>) {
+  fn get_tag(&mut self, acc: &mut Vec<Tag<'s>>) {
     self.skip_trivia();
-    let m = self.matches[self.idx].clone();
+    let Some(m) = self.matches.get(self.idx).cloned() else { return };
     self.idx += 1;
-    let res = match m.a {
+    if let Annot::Add(comp) = m.a {
+      if comp != Comp::Attributes && self.depth >= self.opts.max_nesting {
+        self.idx -= 1;
+        self.get_literal(comp, acc);
+        return;
+      }
+    }
+    self.depth += 1;
+    self.get_tag_impl(m, acc);
+    self.depth -= 1;
+  }
+
+  fn get_tag_impl(&mut self, m: Match, acc: &mut Vec<Tag<'s>>) {
+    let mut res = match m.a {
       Annot::Add(comp) => match comp {
         Comp::CodeBlock => Tag::CodeBlock(self.get_code_block()),
         Comp::Para => Tag::Para(self.get_para()),
+        Comp::Heading => Tag::Heading(self.get_heading(m.range.len())),
         Comp::Verbatim => Tag::Verbatim(self.get_verbatim()),
         Comp::Strong => Tag::Strong(self.get_strong()),
         Comp::Emph => Tag::Emph(self.get_emph()),
@@ -55,239 +154,404 @@ impl Ctx {
         Comp::Linktext => Tag::Link(self.get_link()),
         Comp::Imagetext => Tag::Image(self.get_image()),
         Comp::Url => Tag::Url(self.get_url()),
-        Comp::Attributes => todo!(),
+        Comp::Attributes => {
+          self.idx -= 1;
+          let attrs = self.get_attrs();
+          if let Some(tag) = acc.last_mut() {
+            tag.attrs_mut().extend(attrs);
+          }
+          return;
+        }
         Comp::Span => Tag::Span(self.get_span()),
         Comp::ReferenceDefinition => {
           self.get_reference_definition();
           return;
         }
-        _ => todo!("{comp:?}"),
+        Comp::BlockAttributes => {
+          self.get_block_attrs(m.range.start);
+          return;
+        }
+        _ => {
+          // No dedicated node yet, keep the content.
+          let children = self.get_tags_until(comp);
+          acc.extend(children);
+          return;
+        }
       },
-      Annot::Sub(sub) => unreachable!("-{sub}"),
+      Annot::Sub(sub) => {
+        self.error(m.range.start, format!("unexpected -{sub}"));
+        return;
+      }
       Annot::Atom(atom) => match atom {
         Atom::Str => {
-          let mut text = self.subject[m.range].to_string();
           let attrs = self.get_attrs();
+          let mut start = m.range.start;
           if !attrs.is_empty() {
+            let text = &self.subject[m.range.clone()];
             if let Some(idx) = text.rfind(|it: char| it.is_ascii_whitespace()) {
-              acc.push(Tag::Str(Str { attrs: Attrs::new(), text: text[..idx + 1].to_string() }));
-              text.drain(..idx + 1);
+              let range = start..start + idx + 1;
+              let pos = self.pos(range.clone());
+              acc.push(Tag::Str(Str { attrs: Attrs::new(), pos, text: self.text(range) }));
+              start += idx + 1;
             }
           }
-          Tag::Str(Str { attrs, text })
+          let text = self.text(start..m.range.end);
+          let pos = self.pos(start..self.matches[self.idx - 1].range.end);
+          acc.push(Tag::Str(Str { attrs, pos, text }));
+          return;
         }
         Atom::Emoji => {
           let mut res = Emoji::default();
-          res.alias = self.subject[m.range.start + 1..m.range.end - 1].to_string();
+          res.alias = self.text(m.range.start + 1..m.range.end - 1);
           Tag::Emoji(res)
         }
         Atom::Softbreak => Tag::SoftBreak(SoftBreak::default()),
-        Atom::Class | Atom::Id => return,
-        _ => todo!("{atom:?}"),
+        Atom::EmDash => Tag::EmDash(EmDash::default()),
+        Atom::EnDash => Tag::EnDash(EnDash::default()),
+        Atom::Class | Atom::Id | Atom::Key | Atom::Value => return,
+        _ => {
+          // No dedicated node yet, keep the source text.
+          Tag::Str(Str { text: self.text(m.range.clone()), ..Str::default() })
+        }
       },
     };
+    let range = m.range.start..self.matches[self.idx - 1].range.end;
+    res.set_pos(self.pos(range.clone()));
+    self.note(&res, range);
     acc.push(res)
   }
 
-  fn get_code_block(&mut self) -> CodeBlock {
+  /// Records what [`crate::diagnostics`] needs to know about `tag`.
+  fn note(&mut self, tag: &Tag<'s>, range: Range<usize>) {
+    let kind = match tag {
+      Tag::Link(Link { destination: None, reference: Some(r), .. })
+      | Tag::Image(Image { destination: None, reference: Some(r), .. }) => {
+        Kind::Reference(normalize_label(r))
+      }
+      Tag::Emoji(it) if emoji::find_emoji(&it.alias).is_none() => {
+        Kind::UnknownEmoji(it.alias.to_string())
+      }
+      _ => return,
+    };
+    self.findings.push(Finding { range, kind })
+  }
+
+  /// Source text of a container nested too deep, without recursing into it.
+  fn get_literal(&mut self, comp: Comp, acc: &mut Vec<Tag<'s>>) {
+    let start = self.matches[self.idx].range.start;
+    let mut end = start;
+    let mut depth = 0;
+    let mut close = vec![comp.sub()];
+    if matches!(comp, Comp::Linktext | Comp::Imagetext) {
+      close = vec![Comp::Destination.sub(), Comp::Reference.sub()];
+    }
+    while let Some(m) = self.matches.get(self.idx) {
+      self.idx += 1;
+      end = end.max(m.range.end);
+      if m.is(comp.add()) {
+        depth += 1;
+      } else if m.is(comp.sub()) {
+        depth -= 1;
+      }
+      if depth == 0 && close.contains(&m.a) {
+        break;
+      }
+    }
+    let text =
+      if self.subject.get(start..end).is_some() { self.text(start..end) } else { "".into() };
+    acc.push(Tag::Str(Str { text, pos: self.pos(start..end), ..Str::default() }));
+  }
+
+  fn get_code_block(&mut self) -> CodeBlock<'s> {
     let mut res = CodeBlock::default();
-    let m = self.matches[self.idx].clone();
-    if m.is(Atom::CodeLanguage) {
-      res.lang = Some(self.subject[m.range].to_string());
+    res.attrs = self.take_block_attrs();
+    if self.at(Atom::CodeLanguage) {
+      res.lang = Some(self.text(self.matches[self.idx].range.clone()));
       self.idx += 1;
     }
     res.text = self.get_text_until(Comp::CodeBlock);
     res
   }
 
-  fn get_para(&mut self) -> Para {
+  fn get_para(&mut self) -> Para<'s> {
     let mut res = Para::default();
+    res.attrs = self.take_block_attrs();
     res.children = self.get_tags_until(Comp::Para);
     res
   }
 
-  fn get_verbatim(&mut self) -> Verbatim {
+  fn get_heading(&mut self, level: usize) -> Heading<'s> {
+    let attrs = self.take_block_attrs();
+    let children = self.get_tags_until(Comp::Heading);
+    Heading { attrs, level: level as u32, children, ..Heading::default() }
+  }
+
+  fn get_verbatim(&mut self) -> Verbatim<'s> {
     let mut res = Verbatim::default();
-    res.text = self.get_text_until(Comp::Verbatim);
-    if find(res.text.as_str(), "^ +`").is_match {
-      res.text.remove(0);
-    }
-    if find(res.text.as_str(), "` +$").is_match {
-      res.text.pop();
-    }
+    let text = self.get_text_until(Comp::Verbatim);
+    let start = if find(&text, pat!("^ +`")).is_match { 1 } else { 0 };
+    let end = if find(&text[start..], pat!("` +$")).is_match { text.len() - 1 } else { text.len() };
+    res.text = sub_str(text, start..end);
     res
   }
 
-  fn get_strong(&mut self) -> Strong {
+  fn get_strong(&mut self) -> Strong<'s> {
     let mut res = Strong::default();
     res.children = self.get_tags_until(Comp::Strong);
     res
   }
 
-  fn get_emph(&mut self) -> Emph {
+  fn get_emph(&mut self) -> Emph<'s> {
     let mut res = Emph::default();
     res.children = self.get_tags_until(Comp::Emph);
     res
   }
 
-  fn get_insert(&mut self) -> Insert {
+  fn get_insert(&mut self) -> Insert<'s> {
     let mut res = Insert::default();
     res.children = self.get_tags_until(Comp::Insert);
     res
   }
 
-  fn get_delete(&mut self) -> Delete {
+  fn get_delete(&mut self) -> Delete<'s> {
     let mut res = Delete::default();
     res.children = self.get_tags_until(Comp::Delete);
     res
   }
 
-  fn get_mark(&mut self) -> Mark {
+  fn get_mark(&mut self) -> Mark<'s> {
     let mut res = Mark::default();
     res.children = self.get_tags_until(Comp::Mark);
     res
   }
 
-  fn get_subscript(&mut self) -> Subscript {
+  fn get_subscript(&mut self) -> Subscript<'s> {
     let mut res = Subscript::default();
     res.children = self.get_tags_until(Comp::Subscript);
     res
   }
 
-  fn get_superscript(&mut self) -> Superscript {
+  fn get_superscript(&mut self) -> Superscript<'s> {
     let mut res = Superscript::default();
     res.children = self.get_tags_until(Comp::Superscript);
     res
   }
 
-  fn get_double_quoted(&mut self) -> DoubleQuoted {
+  fn get_double_quoted(&mut self) -> DoubleQuoted<'s> {
     let mut res = DoubleQuoted::default();
     res.children = self.get_tags_until(Comp::DoubleQuoted);
     res
   }
 
-  fn get_link(&mut self) -> Link {
+  fn get_link(&mut self) -> Link<'s> {
     let mut res = Link::default();
     res.children = self.get_tags_until(Comp::Linktext);
     match self.get_dest() {
       LinkDest::Dest(dest) => res.destination = Some(dest),
       LinkDest::Ref(r) => res.reference = Some(r),
-      LinkDest::AutoRef => res.reference = Some(get_string_content(&res.children)),
+      LinkDest::AutoRef => res.reference = Some(Cow::Owned(inline_text(&res.children))),
     }
     res
   }
 
-  fn get_image(&mut self) -> Image {
+  fn get_image(&mut self) -> Image<'s> {
     let mut res = Image::default();
     res.children = self.get_tags_until(Comp::Imagetext);
     match self.get_dest() {
       LinkDest::Dest(dest) => res.destination = Some(dest),
       LinkDest::Ref(r) => res.reference = Some(r),
-      LinkDest::AutoRef => res.reference = Some(get_string_content(&res.children)),
+      LinkDest::AutoRef => res.reference = Some(Cow::Owned(inline_text(&res.children))),
     }
     res
   }
 
-  fn get_dest(&mut self) -> LinkDest {
-    let m = self.matches[self.idx].clone();
+  fn get_dest(&mut self) -> LinkDest<'s> {
+    let Some(m) = self.matches.get(self.idx).cloned() else {
+      self.error(self.subject.len(), "missing link destination".to_string());
+      return LinkDest::AutoRef;
+    };
     self.idx += 1;
     if m.is(Comp::Destination.add()) {
       let dest = self.get_text_until(Comp::Destination);
-      LinkDest::Dest(dest.replace('\n', ""))
+      LinkDest::Dest(if dest.contains('\n') { Cow::Owned(dest.replace('\n', "")) } else { dest })
     } else {
       let r = self.get_text_until(Comp::Reference);
       if r.is_empty() {
         LinkDest::AutoRef
+      } else if r.contains('\n') {
+        LinkDest::Ref(Cow::Owned(r.replace('\n', " ")))
       } else {
-        LinkDest::Ref(r.replace('\n', " "))
+        LinkDest::Ref(r)
       }
     }
   }
 
-  fn get_url(&mut self) -> Url {
+  fn get_url(&mut self) -> Url<'s> {
     let mut res = Url::default();
     res.destination = self.get_text_until(Comp::Url);
     res
   }
 
-  fn get_span(&mut self) -> Span {
+  fn get_span(&mut self) -> Span<'s> {
     let mut res = Span::default();
     res.children = self.get_tags_until(Comp::Span);
     res.attrs = self.get_attrs();
     res
   }
 
-  fn get_attrs(&mut self) -> Attrs {
-    if !self.matches[self.idx].is(Comp::Attributes.add()) {
+  fn get_attrs(&mut self) -> Attrs<'s> {
+    if !self.at(Comp::Attributes.add()) {
       return Attrs::new();
     }
     self.idx += 1;
-    let mut res = Attrs::new();
-    loop {
-      let m = self.matches[self.idx].clone();
-      self.idx += 1;
-      if m.is(Comp::Attributes.sub()) {
-        break;
-      }
-      if m.is(Atom::Class) {
-        match res.entry("class".to_string()) {
-          indexmap::map::Entry::Occupied(mut it) => {
-            it.insert(format!("{} {}", it.get(), &self.subject[m.range.clone()]));
-          }
-          indexmap::map::Entry::Vacant(it) => {
-            it.insert(self.subject[m.range.clone()].to_string());
-          }
-        }
-      } else if m.is(Atom::Id) {
-        res.insert("id".to_string(), self.subject[m.range].to_string());
-      } else if m.is(Atom::Key) {
-        let key = self.subject[m.range].to_string();
-        let m = self.matches[self.idx].clone();
-        self.idx += 1;
-        let value = self.subject[m.range].to_string();
-        res.insert(key, value);
+    let start = self.idx;
+    while !self.at(Comp::Attributes.sub()) {
+      if self.idx == self.matches.len() {
+        self.error(self.subject.len(), "unclosed attributes".to_string());
+        return Attrs::new();
       }
+      self.idx += 1;
     }
-    res
+    self.idx += 1;
+    collect_attrs(self.src, &self.matches[start..self.idx - 1])
   }
 
   fn get_reference_definition(&mut self) {
-    let mut res = ReferenceDefinition::default();
+    let mut res = ReferenceDefinition { attrs: self.take_block_attrs(), ..Default::default() };
+    let start = self.matches[self.idx - 1].range.start;
+    if !self.at(Atom::ReferenceKey) {
+      self.error(start, "missing reference key".to_string());
+      return;
+    }
     let key = self.matches[self.idx].clone();
     self.idx += 1;
-    loop {
+    while self.at(Atom::ReferenceValue) {
       let m = self.matches[self.idx].clone();
-      if !m.is(Atom::ReferenceValue) {
-        break;
+      self.idx += 1;
+      res.destination = if res.destination.is_empty() {
+        self.text(m.range)
+      } else {
+        Cow::Owned(res.destination.into_owned() + &self.subject[m.range])
+      };
+    }
+    if !self.at(Comp::ReferenceDefinition.sub()) {
+      self.error(start, "unclosed reference definition".to_string());
+      return;
+    }
+    let end = self.matches[self.idx].range.end;
+    res.pos = self.pos(start..end);
+    self.idx += 1;
+    let label = self.text(key.range.start + 1..key.range.end - 1);
+    let key = match normalize_label(&label) {
+      it if it == label => label,
+      it => Cow::Owned(it),
+    };
+    self.findings.push(Finding { range: start..end, kind: Kind::Definition(key.to_string()) });
+    self.definitions.push((start, key, res));
+  }
+
+  /// Several lines of attributes add up, see [`collect_attrs`].
+  fn get_block_attrs(&mut self, start: usize) {
+    let first = self.idx;
+    while !self.at(Comp::BlockAttributes.sub()) {
+      if self.idx == self.matches.len() {
+        self.error(start, "unclosed block attributes".to_string());
+        return;
       }
       self.idx += 1;
-      res.destination.push_str(&self.subject[m.range]);
     }
-    assert!(self.matches[self.idx].is(Comp::ReferenceDefinition.sub()));
+    self.block_attrs.extend_from_slice(&self.matches[first..self.idx]);
     self.idx += 1;
-    self.references.insert(self.subject[key.range.start + 1..key.range.end - 1].to_string(), res);
   }
 
-  fn get_tags_until(&mut self, comp: Comp) -> Vec<Tag> {
+  fn take_block_attrs(&mut self) -> Attrs<'s> {
+    let matches = std::mem::take(&mut self.block_attrs);
+    collect_attrs(self.src, &matches)
+  }
+
+  /// Containers can cross, like an emphasis opened in a link text and closed
+  /// in its destination. Closing an outer container closes the inner ones.
+  fn get_tags_until(&mut self, comp: Comp) -> Vec<Tag<'s>> {
     let mut res = vec![];
-    while !self.matches[self.idx].is(comp.sub()) {
+    self.open.push(comp);
+    while !self.at(comp.sub()) {
+      if self.idx == self.matches.len() {
+        self.error(self.subject.len(), format!("unclosed {comp}"));
+        self.open.pop();
+        return res;
+      }
+      if let Annot::Sub(sub) = self.matches[self.idx].a {
+        if self.open.contains(&sub) {
+          self.open.pop();
+          return res;
+        }
+      }
       self.get_tag(&mut res)
     }
+    self.open.pop();
     self.idx += 1;
     res
   }
 
-  fn get_text_until(&mut self, comp: Comp) -> String {
-    let mut res = String::new();
+  /// Borrows the text as long as the matches are contiguous.
+  fn get_text_until(&mut self, comp: Comp) -> Cow<'s, str> {
+    let mut range: Option<Range<usize>> = None;
+    let mut owned: Option<String> = None;
     loop {
-      let m = self.matches[self.idx].clone();
+      let Some(m) = self.matches.get(self.idx).cloned() else {
+        self.error(self.subject.len(), format!("unclosed {comp}"));
+        break;
+      };
       self.idx += 1;
       if m.is(comp.sub()) {
         break;
       }
-      res.push_str(&self.subject[m.range]);
+      match (&mut owned, &mut range) {
+        (Some(buf), _) => buf.push_str(&self.subject[m.range]),
+        (None, Some(prev)) if prev.end == m.range.start => prev.end = m.range.end,
+        (None, Some(prev)) => {
+          owned = Some(format!("{}{}", &self.subject[prev.clone()], &self.subject[m.range]))
+        }
+        (None, None) => range = Some(m.range),
+      }
+    }
+    match (owned, range) {
+      (Some(buf), _) => Cow::Owned(buf),
+      (None, Some(range)) => self.text(range),
+      (None, None) => Cow::Borrowed(""),
     }
-    res
+  }
+
+  /// Borrows from `src` unless the range covers the newline we appended.
+  fn text(&self, range: Range<usize>) -> Cow<'s, str> {
+    match self.src.get(range.clone()) {
+      Some(it) => Cow::Borrowed(it),
+      None => Cow::Owned(self.subject[range].to_string()),
+    }
+  }
+
+  fn at(&self, annot: impl Into<Annot>) -> bool {
+    self.matches.get(self.idx).is_some_and(|it| it.is(annot))
+  }
+
+  fn error(&mut self, offset: usize, message: String) {
+    if self.error.is_none() {
+      self.error = Some(ParseError { offset, message });
+    }
+  }
+
+  fn pos(&self, range: Range<usize>) -> Option<SourceSpan> {
+    if !self.opts.source_positions {
+      return None;
+    }
+    Some(SourceSpan { start: self.source_pos(range.start), end: self.source_pos(range.end) })
+  }
+
+  fn source_pos(&self, offset: usize) -> SourcePos {
+    let line = self.line_starts.partition_point(|&it| it <= offset);
+    let line_start = self.line_starts[line - 1];
+    let col = self.subject[line_start..offset].chars().count() + 1;
+    SourcePos { line, col, offset }
   }
 
   fn skip_trivia(&mut self) {
@@ -302,21 +566,131 @@ impl Ctx {
   }
 }
 
-pub(crate) fn get_string_content(tags: &[Tag]) -> String {
-  let mut res = String::new();
-  for tag in tags {
-    match tag {
-      Tag::SoftBreak(_) => res.push('\n'),
-      Tag::Str(str) => res.push_str(&str.text),
-      Tag::Emph(emph) => res.push_str(&get_string_content(&emph.children)),
-      _ => (),
+/// Top-level tags, borrowed or owned, which [`sections`] groups.
+pub(crate) trait SectionTag: Sized {
+  /// Level and text of a heading.
+  fn heading(&self) -> Option<(u32, String)>;
+  /// Removes the explicit id of a heading, which goes to its section.
+  fn take_id(&mut self) -> Option<String>;
+  fn section(id: String, children: Vec<Self>) -> Self;
+}
+
+/// Wraps each heading and what follows, up to a heading of the same or a
+/// higher level, in a section with the heading's id. Also returns the
+/// implicit references to headings, as `(key, destination)` pairs.
+///
+/// Headings without an id get one from their text, unique in the document.
+pub(crate) fn sections<T: SectionTag>(children: Vec<T>) -> (Vec<T>, Vec<(String, String)>) {
+  fn close<T: SectionTag>(stack: &mut Vec<(u32, String, Vec<T>)>) {
+    let (_, id, children) = stack.pop().unwrap();
+    stack.last_mut().unwrap().2.push(T::section(id, children))
+  }
+
+  let mut identifiers = HashSet::new();
+  let mut references = Vec::new();
+  let mut stack = vec![(0, String::new(), Vec::new())];
+  for mut tag in children {
+    let Some((level, text)) = tag.heading() else {
+      stack.last_mut().unwrap().2.push(tag);
+      continue;
+    };
+    while stack.last().unwrap().0 >= level {
+      close(&mut stack);
     }
+    let text = text.trim();
+    let id = tag.take_id().unwrap_or_else(|| get_identifier(&identifiers, text));
+    identifiers.insert(id.clone());
+    references.push((normalize_label(text), format!("#{id}")));
+    stack.push((level, id, vec![tag]));
+  }
+  while stack.len() > 1 {
+    close(&mut stack);
+  }
+  (stack.pop().unwrap().2, references)
+}
+
+/// An id from the heading text not in `identifiers`, like `get_identifier` in
+/// djot.lua.
+pub(crate) fn get_identifier(identifiers: &HashSet<String>, text: &str) -> String {
+  let text: String =
+    text.chars().filter(|it| !"][~!@#$%^&*(){}`,.<>\\|=+/?".contains(*it)).collect();
+  let base = text.split_whitespace().collect::<Vec<_>>().join("-");
+  let mut res = base.clone();
+  let mut i = 0;
+  while res.is_empty() || identifiers.contains(&res) {
+    i += 1;
+    res = format!("{}-{i}", if base.is_empty() { "s" } else { &base });
   }
   res
 }
 
-enum LinkDest {
-  Dest(String),
-  Ref(String),
+fn section_pos(children: &[Option<SourceSpan>]) -> Option<SourceSpan> {
+  let start = children.first().copied()??.start;
+  let end = children.last().copied()??.end;
+  Some(SourceSpan { start, end })
+}
+
+impl<'s> SectionTag for Tag<'s> {
+  fn heading(&self) -> Option<(u32, String)> {
+    match self {
+      Tag::Heading(heading) => Some((heading.level, inline_text(&heading.children))),
+      _ => None,
+    }
+  }
+
+  fn take_id(&mut self) -> Option<String> {
+    self.attrs_mut().shift_remove("id").map(Cow::into_owned)
+  }
+
+  fn section(id: String, children: Vec<Tag<'s>>) -> Tag<'s> {
+    Tag::Section(Section {
+      attrs: Attrs::from([(Cow::Borrowed("id"), Cow::Owned(id))]),
+      pos: section_pos(&children.iter().map(Tag::pos).collect::<Vec<_>>()),
+      children,
+    })
+  }
+}
+
+impl SectionTag for crate::ast::Tag {
+  fn heading(&self) -> Option<(u32, String)> {
+    match self {
+      crate::ast::Tag::Heading(heading) => {
+        Some((heading.level, text::inline_text(&heading.children)))
+      }
+      _ => None,
+    }
+  }
+
+  fn take_id(&mut self) -> Option<String> {
+    self.attrs_mut().shift_remove("id")
+  }
+
+  fn section(id: String, children: Vec<crate::ast::Tag>) -> crate::ast::Tag {
+    crate::ast::Tag::Section(crate::ast::Section {
+      attrs: crate::ast::Attrs::from([("id".to_string(), id)]),
+      pos: section_pos(&children.iter().map(crate::ast::Tag::pos).collect::<Vec<_>>()),
+      children,
+    })
+  }
+}
+
+/// [`text::inline_text`] of borrowed tags, which are rare enough (headings
+/// and `[text][]` links) to copy.
+fn inline_text(tags: &[Tag<'_>]) -> String {
+  let tags: Vec<_> = tags.iter().cloned().map(Tag::into_owned).collect();
+  text::inline_text(&tags)
+}
+
+/// `&text[range]`, without copying borrowed text.
+fn sub_str(text: Cow<'_, str>, range: Range<usize>) -> Cow<'_, str> {
+  match text {
+    Cow::Borrowed(it) => Cow::Borrowed(&it[range]),
+    Cow::Owned(it) => Cow::Owned(it[range].to_string()),
+  }
+}
+
+enum LinkDest<'s> {
+  Dest(Cow<'s, str>),
+  Ref(Cow<'s, str>),
   AutoRef,
 }
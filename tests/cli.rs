@@ -0,0 +1,140 @@
+use xshell::{cmd, Shell};
+
+fn djot() -> &'static str {
+  env!("CARGO_BIN_EXE_djot")
+}
+
+#[test]
+#[cfg(unix)]
+fn filters() {
+  let sh = Shell::new().unwrap();
+  let dir = sh.create_temp_dir().unwrap();
+  let shout = dir.path().join("shout");
+  sh.write_file(&shout, "#!/bin/sh\nsed 's/\"text\": \"\\(.*\\)\"/\"text\": \"\\1!\"/'\n").unwrap();
+  let emph = dir.path().join("emph");
+  sh.write_file(&emph, "#!/bin/sh\nsed 's/\"para\"/\"emph\"/'\n").unwrap();
+  cmd!(sh, "chmod +x {shout} {emph}").run().unwrap();
+
+  let djot = djot();
+  let html = cmd!(sh, "{djot} --filter {shout} -f {emph}").stdin("hello").read().unwrap();
+  assert_eq!(html, "<em>hello!</em>");
+
+  let fail = cmd!(sh, "{djot} --filter false").stdin("hello").ignore_stderr().read();
+  assert!(fail.is_err());
+}
+
+#[test]
+fn formats_and_outputs() {
+  let sh = Shell::new().unwrap();
+  let dir = sh.create_temp_dir().unwrap();
+  let djot = djot();
+
+  let text = cmd!(sh, "{djot} --to text").stdin("_hi_ there").read().unwrap();
+  assert_eq!(text, "hi there");
+  let roundtrip = cmd!(sh, "{djot} -t djot").stdin("_hi_ there").read().unwrap();
+  assert_eq!(roundtrip, "_hi_ there");
+  let bad = cmd!(sh, "{djot} --to pdf").stdin("").ignore_stderr().ignore_status().output().unwrap();
+  assert_eq!(bad.status.code(), Some(2));
+
+  sh.change_dir(dir.path());
+  sh.write_file("docs/a.dj", "a").unwrap();
+  sh.write_file("docs/sub/b.dj", "*b*").unwrap();
+  cmd!(sh, "{djot} docs/a.dj -o single.html").run().unwrap();
+  assert_eq!(sh.read_file("single.html").unwrap(), "<p>a</p>\n");
+  cmd!(sh, "{djot} docs/a.dj docs/sub/b.dj -o out").run().unwrap();
+  assert_eq!(sh.read_file("out/a.html").unwrap(), "<p>a</p>\n");
+  assert_eq!(sh.read_file("out/sub/b.html").unwrap(), "<p><strong>b</strong></p>\n");
+  cmd!(sh, "{djot} docs/sub/b.dj --to json -o json/").run().unwrap();
+  assert!(sh.path_exists("json/b.json"));
+
+  // The other files are still converted.
+  let missing = cmd!(sh, "{djot} docs/a.dj docs/missing.dj docs/sub/b.dj -o partial")
+    .ignore_stderr()
+    .ignore_status()
+    .output();
+  assert_eq!(missing.unwrap().status.code(), Some(1));
+  assert!(sh.path_exists("partial/a.html") && sh.path_exists("partial/sub/b.html"));
+}
+
+#[test]
+fn standalone() {
+  let sh = Shell::new().unwrap();
+  let dir = sh.create_temp_dir().unwrap();
+  let djot = djot();
+  sh.change_dir(dir.path());
+
+  sh.write_file("notes.dj", "hi").unwrap();
+  let page = cmd!(sh, "{djot} -s notes.dj --css style.css -V lang=en").read().unwrap();
+  assert!(page.starts_with("<!DOCTYPE html>\n<html lang=\"en\">"), "{page}");
+  assert!(page.contains("<title>notes</title>"), "{page}");
+  assert!(page.contains("<link rel=\"stylesheet\" href=\"style.css\">"), "{page}");
+
+  sh.write_file("titled.dj", "---\ntitle: A & B\n---\nhi").unwrap();
+  let page = cmd!(sh, "{djot} -s titled.dj").read().unwrap();
+  assert!(page.contains("<title>A &amp; B</title>"), "{page}");
+
+  sh.write_file("guide.dj", "# One\n\n## Two\n\n### Three\n").unwrap();
+  let page = cmd!(sh, "{djot} -s --toc-depth 2 guide.dj").read().unwrap();
+  assert!(page.contains("<nav id=\"toc\">\n<ul>\n<li><a href=\"#One\">"), "{page}");
+  assert!(page.contains("#Two") && !page.contains("#Three"), "{page}");
+  let fragment = cmd!(sh, "{djot} --toc guide.dj").read().unwrap();
+  assert!(fragment.starts_with("<nav id=\"toc\">") && fragment.contains("#Three"), "{fragment}");
+
+  sh.write_file("page.html", "<h1>$title$</h1>$body$").unwrap();
+  let page = cmd!(sh, "{djot} --template page.html --title Hello").stdin("hi").read().unwrap();
+  assert_eq!(page, "<h1>Hello</h1><p>hi</p>");
+
+  let bad = cmd!(sh, "{djot} -s --to json").stdin("").ignore_stderr().ignore_status().output();
+  assert_eq!(bad.unwrap().status.code(), Some(2));
+  sh.write_file("bad.html", "$if(x)$").unwrap();
+  let bad =
+    cmd!(sh, "{djot} --template bad.html").stdin("").ignore_stderr().ignore_status().output();
+  assert_eq!(bad.unwrap().status.code(), Some(1));
+}
+
+#[test]
+fn check() {
+  let sh = Shell::new().unwrap();
+  let dir = sh.create_temp_dir().unwrap();
+  let djot = djot();
+
+  let clean = cmd!(sh, "{djot} check").stdin("[a][]\n\n[a]: /a\n").read().unwrap();
+  assert_eq!(clean, "");
+  let hint = cmd!(sh, "{djot} check").stdin("[a]: /a\n").read().unwrap();
+  assert_eq!(hint, "<stdin>:1:1: hint: reference `a` is never used");
+
+  sh.change_dir(dir.path());
+  sh.write_file("a.dj", "hi :nope:\n\n[b][] {.c\n").unwrap();
+  let out = cmd!(sh, "{djot} check a.dj").ignore_status().output().unwrap();
+  assert_eq!(out.status.code(), Some(1));
+  assert_eq!(
+    String::from_utf8(out.stdout).unwrap(),
+    "\
+a.dj:1:4: warning: unknown emoji `:nope:`
+a.dj:3:1: error: undefined reference `b`
+a.dj:3:7: warning: unclosed attributes
+"
+  );
+}
+
+#[test]
+fn fmt() {
+  let sh = Shell::new().unwrap();
+  let dir = sh.create_temp_dir().unwrap();
+  let djot = djot();
+
+  let formatted =
+    cmd!(sh, "{djot} fmt --width 10").stdin("{.b #a}\none two three\n").read().unwrap();
+  assert_eq!(formatted, "{#a .b}\none two\nthree");
+  let unchanged = cmd!(sh, "{djot} fmt -w 0").stdin("one\ntwo\n").read().unwrap();
+  assert_eq!(unchanged, "one\ntwo");
+
+  sh.change_dir(dir.path());
+  sh.write_file("a.dj", "[r]: /r\n\n[x][r]\n").unwrap();
+  sh.write_file("b.dj", "b\n").unwrap();
+  let check = cmd!(sh, "{djot} fmt --check a.dj b.dj").ignore_stderr().ignore_status().output();
+  assert_eq!(check.unwrap().status.code(), Some(1));
+  cmd!(sh, "{djot} fmt a.dj b.dj").run().unwrap();
+  assert_eq!(sh.read_file("a.dj").unwrap(), "[x][r]\n\n[r]: /r\n");
+  cmd!(sh, "{djot} fmt --check a.dj b.dj").run().unwrap();
+}
@@ -0,0 +1,146 @@
+use std::{
+  io::{Read, Write},
+  process::{Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+/// Sends the messages to a fresh server and returns everything it sent back.
+fn session(messages: &[Value]) -> (Vec<Value>, Option<i32>) {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_djot-lsp"))
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut stdin = child.stdin.take().unwrap();
+  for message in messages {
+    let json = message.to_string();
+    write!(stdin, "Content-Length: {}\r\n\r\n{json}", json.len()).unwrap();
+  }
+  drop(stdin);
+  let mut output = String::new();
+  child.stdout.take().unwrap().read_to_string(&mut output).unwrap();
+  let status = child.wait().unwrap();
+
+  let mut res = Vec::new();
+  let mut rest = output.as_str();
+  while let Some(header) = rest.strip_prefix("Content-Length: ") {
+    let (len, body) = header.split_once("\r\n\r\n").unwrap();
+    let len: usize = len.parse().unwrap();
+    res.push(serde_json::from_str(&body[..len]).unwrap());
+    rest = &body[len..];
+  }
+  assert_eq!(rest, "");
+  (res, status.code())
+}
+
+fn request(id: u32, method: &str, params: Value) -> Value {
+  json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+  json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn at(line: u32, character: u32) -> Value {
+  json!({ "textDocument": { "uri": "file:///a.dj" }, "position": { "line": line, "character": character } })
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+  json!({
+    "start": { "line": start.0, "character": start.1 },
+    "end": { "line": end.0, "character": end.1 },
+  })
+}
+
+#[test]
+fn lsp() {
+  let text = "# Intro\n\nSee [the docs][docs] and [Intro][] :smile: :nope:\n\n## Ünïcode\n\n[more][] [x][d\n\n[docs]: /docs\n";
+  let doc = json!({ "uri": "file:///a.dj" });
+  let (replies, code) = session(&[
+    request(1, "initialize", json!({ "capabilities": {} })),
+    notification("initialized", json!({})),
+    notification(
+      "textDocument/didOpen",
+      json!({ "textDocument": { "uri": "file:///a.dj", "languageId": "djot", "version": 1, "text": text } }),
+    ),
+    request(2, "textDocument/documentSymbol", json!({ "textDocument": doc })),
+    request(3, "textDocument/definition", at(2, 6)),
+    request(4, "textDocument/definition", at(2, 27)),
+    request(5, "textDocument/completion", at(6, 14)),
+    request(
+      6,
+      "textDocument/completion",
+      json!({ "textDocument": doc, "position": { "line": 2, "character": 40 } }),
+    ),
+    request(7, "textDocument/hover", at(2, 0)),
+    // Defines `more`, making `[more][]` a link.
+    notification(
+      "textDocument/didChange",
+      json!({
+        "textDocument": { "uri": "file:///a.dj", "version": 2 },
+        "contentChanges": [{ "range": range((8, 0), (8, 0)), "text": "[more]: /more\n" }],
+      }),
+    ),
+    request(8, "textDocument/definition", at(6, 2)),
+    request(9, "unknown/method", json!({})),
+    request(10, "shutdown", Value::Null),
+    notification("exit", Value::Null),
+  ]);
+  assert_eq!(code, Some(0));
+  let reply = |id: u32| replies.iter().find(|it| it["id"] == id).unwrap()["result"].clone();
+  let diagnostics: Vec<_> =
+    replies.iter().filter(|it| it["method"] == "textDocument/publishDiagnostics").collect();
+  assert_eq!(diagnostics.len(), 2);
+
+  assert_eq!(reply(1)["capabilities"]["definitionProvider"], true);
+  let messages = |it: &Value| -> Vec<String> {
+    let diagnostics = it["params"]["diagnostics"].as_array().unwrap();
+    diagnostics
+      .iter()
+      .map(|it| format!("{} {}", it["severity"], it["message"].as_str().unwrap()))
+      .collect()
+  };
+  assert_eq!(
+    messages(diagnostics[0]),
+    ["2 unknown emoji `:nope:`", "1 undefined reference `more`"]
+  );
+  assert_eq!(messages(diagnostics[1]), ["2 unknown emoji `:nope:`"]);
+  assert_eq!(diagnostics[0]["params"]["diagnostics"][1]["range"], range((6, 0), (6, 7)));
+
+  let symbols = reply(2);
+  assert_eq!(symbols[0]["name"], "Intro");
+  assert_eq!(symbols[0]["selectionRange"], range((0, 0), (0, 7)));
+  assert_eq!(symbols[0]["children"][0]["name"], "Ünïcode");
+  assert_eq!(symbols[0]["children"][0]["detail"], "h2");
+
+  assert_eq!(reply(3), json!({ "uri": "file:///a.dj", "range": range((8, 0), (9, 0)) }));
+  assert_eq!(reply(4), json!({ "uri": "file:///a.dj", "range": range((0, 0), (0, 7)) }));
+  assert_eq!(reply(8), json!({ "uri": "file:///a.dj", "range": range((8, 0), (9, 0)) }));
+
+  let labels = |id| -> Vec<String> {
+    reply(id)
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|it| it["label"].as_str().unwrap().to_string())
+      .collect()
+  };
+  assert_eq!(labels(5), ["docs"]);
+  assert_eq!(reply(5)[0]["textEdit"]["range"], range((6, 13), (6, 14)));
+  assert!(labels(6).contains(&"smile".to_string()));
+  assert!(labels(6).iter().all(|it| it.starts_with("smi")));
+
+  let hover = reply(7);
+  assert_eq!(hover["contents"]["value"], "```html\n<p>See <a href=\"/docs\">the docs</a> and <a href=\"#Intro\">Intro</a> 😄 :nope:</p>\n```");
+
+  let error = replies.iter().find(|it| it["id"] == 9).unwrap();
+  assert_eq!(error["error"]["code"], -32601);
+}
+
+#[test]
+fn exit_without_shutdown() {
+  let (replies, code) = session(&[notification("exit", Value::Null)]);
+  assert!(replies.is_empty());
+  assert_eq!(code, Some(1));
+}
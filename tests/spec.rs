@@ -1,6 +1,26 @@
-use std::{fs, path::PathBuf};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// Output of the reference implementation, vendored in `tests/data/ref`.
+///
+/// Run with `UPDATE_REF=1` to regenerate, which requires `git` and `lua`.
+fn ref_output(file_stem: &str, idx: usize, source: &str, matches: bool) -> String {
+  let dir = Path::new("./tests/data/ref");
+  let ext = if matches { "matches" } else { "html" };
+  let path = dir.join(format!("{file_stem}.{idx}.{ext}"));
+  if std::env::var("UPDATE_REF").is_ok() {
+    let output = to_ref_html(source, matches);
+    fs::create_dir_all(dir).unwrap();
+    fs::write(&path, &output).unwrap();
+    return output;
+  }
+  fs::read_to_string(&path).unwrap_or_else(|_| {
+    panic!("\nNo reference output {}, regenerate with UPDATE_REF=1\n", path.display())
+  })
+}
 
-#[allow(unused)]
 fn to_ref_html(source: &str, matches: bool) -> String {
   let sh = xshell::Shell::new().unwrap();
   if !sh.path_exists("ref") {
@@ -18,66 +38,703 @@ fn to_ref_html(source: &str, matches: bool) -> String {
 
 struct TestOpts {
   debug_ast: bool,
-  ref_matches: bool,
   parse: djot::ParseOpts,
 }
 
 #[test]
 fn spec_tests() {
-  let opts =
-    TestOpts { debug_ast: true, ref_matches: true, parse: djot::ParseOpts { debug_matches: true } };
+  let opts = TestOpts {
+    debug_ast: true,
+    parse: djot::ParseOpts {
+      debug_matches: true,
+      source_positions: true,
+      ..djot::ParseOpts::default()
+    },
+  };
 
   let mut last_fail = LastFail::load();
-  let sh = xshell::Shell::new().unwrap();
   let mut total = 0;
-  for path in sh.read_dir("./tests/data").unwrap() {
-    if path.extension().unwrap_or_default() == "test" {
-      let file_stem = path.file_stem().unwrap_or_default().to_str().unwrap_or_default();
-      let source = fs::read_to_string(&path).unwrap();
-      for (i, test_case) in parse_test(source.as_str()).into_iter().enumerate() {
-        if last_fail.skip(file_stem, i) {
-          continue;
-        }
-        let mut debug = String::new();
-        let doc = djot::Document::parse_opts(opts.parse.clone(), &test_case.djot);
-        debug.push_str(&doc.debug);
-        if opts.debug_ast {
-          debug.push_str(&doc.to_json());
-        }
-        let got = doc.to_html();
-        let want = test_case.html.as_str();
-        let ref_html = to_ref_html(&test_case.djot, false);
-        if opts.ref_matches {
-          debug.push_str(&format!("Ref Matches:\n{}-----", to_ref_html(&test_case.djot, true)));
-        }
-        if want != ref_html.as_str() {
-          panic!(
-            "\nReference mismatch in {}\nRef:\n{ref_html}-----\nWant:\n{want}-----\n",
-            file_stem
-          )
+  for_each_test_case(|file_stem, i, test_case| {
+    if last_fail.skip(file_stem, i) {
+      return;
+    }
+    let mut debug = String::new();
+    let doc = djot::Document::try_parse_opts(opts.parse.clone(), &test_case.djot)
+      .unwrap_or_else(|err| panic!("\n{err} in {file_stem}\nSource:\n{}", test_case.djot));
+    debug.push_str(&doc.debug);
+    if test_case.matches {
+      // `[matches]` cases spell out the match stream instead of HTML.
+      check_matches(file_stem, &test_case.djot, &test_case.html, &doc.debug);
+      last_fail.test_ok();
+      total += 1;
+      return;
+    }
+    if opts.debug_ast {
+      debug.push_str(&doc.to_json());
+    }
+    let json = doc.to_json();
+    let roundtrip = djot::Document::from_json(&json).unwrap();
+    assert_eq!(roundtrip.to_json(), json, "json round-trip mismatch in {file_stem}");
+    check_events(&test_case.djot);
+    #[cfg(feature = "fuzz")]
+    {
+      djot::fuzz::parse(&test_case.djot);
+      djot::fuzz::to_html(&test_case.djot);
+      djot::fuzz::to_json(&test_case.djot);
+    }
+    let got = doc.to_html();
+    let want = test_case.html.as_str();
+    if got.as_str() != want {
+      let mut msg = format!(
+        "\nMismatch in {}\nSource:\n{}-----\nWant:\n{want}-----\nGot:\n{got}-----\n",
+        file_stem, test_case.djot,
+      );
+      if !debug.is_empty() {
+        msg = format!("{msg}Debug:\n{debug}-----\n")
+      }
+      panic!("{msg}")
+    }
+    last_fail.test_ok();
+    total += 1;
+  });
+  eprintln!("total tests: {total}");
+}
+
+/// Checks the expected HTML and our match stream against djot.lua.
+///
+/// Ignored by default as it needs `tests/data/ref`, CI runs it with
+/// `UPDATE_REF=1` to compare against a fresh checkout of djot.
+#[test]
+#[ignore]
+fn reference_implementation() {
+  let opts = djot::ParseOpts { debug_matches: true, ..djot::ParseOpts::default() };
+  for_each_test_case(|file_stem, i, test_case| {
+    if test_case.matches {
+      return;
+    }
+    let want = test_case.html.as_str();
+    let ref_html = ref_output(file_stem, i, &test_case.djot, false);
+    if want != ref_html {
+      panic!("\nReference mismatch in {file_stem}\nRef:\n{ref_html}-----\nWant:\n{want}-----\n")
+    }
+    let doc = djot::Document::parse_opts(opts.clone(), &test_case.djot);
+    let ref_matches = ref_output(file_stem, i, &test_case.djot, true);
+    check_matches(file_stem, &test_case.djot, &ref_matches, &doc.debug);
+  })
+}
+
+/// Compares `ParseOpts::debug_matches` output with `lua ./bin/main.lua -m`.
+fn check_matches(file_stem: &str, source: &str, want: &str, debug: &str) {
+  // Our dump also shows the matched text, drop it.
+  let normalize = |dump: &str| {
+    dump.lines().map(|line| line.split_whitespace().take(2).collect::<Vec<_>>().join(" ")).collect()
+  };
+  let want: Vec<String> = normalize(want);
+  let got: Vec<String> = normalize(debug);
+  if want == got {
+    return;
+  }
+  let mut diff = String::new();
+  for i in 0..want.len().max(got.len()) {
+    match (want.get(i), got.get(i)) {
+      (Some(w), Some(g)) if w == g => diff.push_str(&format!("  {w}\n")),
+      (w, g) => {
+        if let Some(w) = w {
+          diff.push_str(&format!("- {w}\n"));
         }
-        if got.as_str() != want {
-          let mut msg = format!(
-            "\nMismatch in {}\nSource:\n{}-----\nWant:\n{want}-----\nGot:\n{got}-----\n",
-            file_stem, test_case.djot,
-          );
-          if !debug.is_empty() {
-            msg = format!("{msg}Debug:\n{debug}-----\n")
-          }
-          panic!("{msg}")
+        if let Some(g) = g {
+          diff.push_str(&format!("+ {g}\n"));
         }
-        last_fail.test_ok();
-        total += 1;
       }
     }
   }
-  eprintln!("total tests: {total}");
+  panic!(
+    "\nMatches mismatch in {file_stem}\nSource:\n{source}-----\nDiff (-ref +got):\n{diff}-----\n"
+  )
+}
+
+#[test]
+fn malformed_input() {
+  for text in ["", "\n\n", "+x+", "-a", "'quoted'", "--- ...", "a\\\nb", "x\\ y", "я=~`[", "[a](b"]
+  {
+    let doc = djot::Document::try_parse(text).unwrap_or_else(|err| panic!("{text:?}: {err}"));
+    doc.to_html();
+  }
+
+  // Containers can cross, closing the outer one closes the inner ones.
+  let html = |text| djot::Document::try_parse(text).unwrap().to_html();
+  assert_eq!(html("-[.#}{+]('+ %)^"), "<p>-<a href=\"'+ %\">.#}<ins></ins></a>^</p>\n");
+  assert_eq!(html("[|**._](*')"), "<p><a href=\"*'\">|*<strong>._</strong></a></p>\n");
+  assert_eq!(html("[~*^|%]['*\n=0(*]"), "<p><a>~<strong>^|%</strong></a></p>\n");
+  assert_eq!(html("*[closed](hello*)"), "<p><strong>[closed](hello</strong>)</p>\n");
+}
+
+#[test]
+fn limits() {
+  let parse = |opts: djot::ParseOpts, text: &str| {
+    let doc = djot::Document::try_parse_opts(opts, text).unwrap_or_else(|err| panic!("{err}"));
+    doc.to_html()
+  };
+  let opts = |max_nesting, max_input_bytes, max_matches| djot::ParseOpts {
+    max_nesting,
+    max_input_bytes,
+    max_matches,
+    ..djot::ParseOpts::default()
+  };
+
+  let html = parse(opts(3, usize::MAX, usize::MAX), "_a *b _c [d](e) c_ b* a_");
+  assert_eq!(html, "<p><em>a <strong>b _c [d](e) c_ b</strong> a</em></p>\n");
+  let html = parse(opts(2, usize::MAX, usize::MAX), "x [_a_](b) y");
+  assert_eq!(html, "<p>x <a href=\"b\">_a_</a> y</p>\n");
+  let html = parse(opts(1, usize::MAX, usize::MAX), "x [_a_](b) y");
+  assert_eq!(html, "<p>x [_a_](b) y</p>\n");
+
+  let html = parse(opts(50, 8, usize::MAX), "_a_\n\n_b_\n_c_\n");
+  assert_eq!(html, "<p><em>a</em></p>\n<p>_b_\n_c_</p>\n");
+  let html = parse(opts(50, usize::MAX, 4), "_a_ _b_\n\n_c_\n");
+  assert_eq!(html, "<p><em>a</em> _b_</p>\n<p>_c_</p>\n");
+
+  // Neither deep nesting nor piles of openers blow up with the defaults.
+  let text = "_*{=^~".repeat(2_000) + "x" + &"~^=}*_".repeat(2_000);
+  parse(djot::ParseOpts::default(), &text);
+  let text = "[".repeat(10_000) + "x" + &"]{.a}".repeat(10_000);
+  let html = parse(djot::ParseOpts::default(), &text);
+  let doc = djot::Document::parse(&text);
+  assert_eq!(djot::Document::from_json(&doc.to_json()).unwrap().to_html(), html);
+}
+
+#[test]
+fn source_positions() {
+  let opts = djot::ParseOpts { source_positions: true, ..djot::ParseOpts::default() };
+  let doc = djot::Document::parse_opts(opts, "hi\nthere _вы_\n\n[r]: /url\n");
+  let span = |tag: &djot::ast::Tag| {
+    let pos = tag.pos().unwrap();
+    (pos.start.line, pos.start.col, pos.start.offset, pos.end.line, pos.end.col, pos.end.offset)
+  };
+
+  let djot::ast::Tag::Para(para) = &doc.children[0] else { panic!() };
+  assert_eq!(span(&doc.children[0]), (1, 1, 0, 2, 11, 15));
+  assert_eq!(span(&para.children[2]), (2, 1, 3, 2, 7, 9));
+  assert_eq!(span(&para.children[3]), (2, 7, 9, 2, 11, 15));
+  let r = doc.references["r"].pos.unwrap();
+  assert_eq!((r.start.line, r.start.col, r.end.line, r.end.col), (4, 1, 5, 1));
+
+  assert!(djot::Document::parse("hi").children[0].pos().is_none());
+}
+
+fn check_events(source: &str) {
+  let mut stack = Vec::new();
+  for event in djot::events::Events::parse(source) {
+    match event {
+      djot::events::Event::Start(container, _) => stack.push(container),
+      djot::events::Event::End(container) => assert_eq!(stack.pop(), Some(container)),
+      _ => (),
+    }
+  }
+  assert_eq!(stack, []);
+}
+
+#[test]
+fn events() {
+  use djot::events::{Atom, Container, Event, Events};
+
+  let attrs = |k: &str, v: &str| [(k.to_string(), v.to_string())].into_iter().collect();
+  let events = Events::parse("[hi]{.x} *a*\nb c{#y}").collect::<Vec<_>>();
+  assert_eq!(
+    events,
+    [
+      Event::Start(Container::Para, Default::default()),
+      Event::Start(Container::Span, attrs("class", "x")),
+      Event::Text("hi"),
+      Event::End(Container::Span),
+      Event::Text(" "),
+      Event::Start(Container::Strong, Default::default()),
+      Event::Text("a"),
+      Event::End(Container::Strong),
+      Event::Atom(Atom::Softbreak, "\n"),
+      Event::Text("b "),
+      Event::Start(Container::Span, attrs("id", "y")),
+      Event::Text("c"),
+      Event::End(Container::Span),
+      Event::End(Container::Para),
+    ]
+  );
+}
+
+#[test]
+fn visitors() {
+  use djot::ast::{Fold, Link, Str, Tag, Visitor, VisitorMut};
+
+  struct Texts(Vec<String>);
+  impl Visitor for Texts {
+    fn visit_str(&mut self, it: &Str) {
+      self.0.push(it.text.clone())
+    }
+  }
+
+  struct Shout;
+  impl VisitorMut for Shout {
+    fn visit_str_mut(&mut self, it: &mut Str) {
+      it.text = it.text.to_uppercase()
+    }
+  }
+
+  struct RewriteLinks;
+  impl Fold for RewriteLinks {
+    fn fold_link(&mut self, mut it: Link) -> Tag {
+      it.destination = it.destination.map(|it| it.replace(".dj", ".html"));
+      it.children = self.fold_children(std::mem::take(&mut it.children));
+      Tag::Link(it)
+    }
+  }
+
+  let mut doc = djot::Document::parse("see [the _docs_](./docs.dj)\n");
+  let mut texts = Texts(Vec::new());
+  texts.visit_children(&doc.children);
+  assert_eq!(texts.0, ["see ", "the ", "docs"]);
+
+  Shout.visit_children_mut(&mut doc.children);
+  doc.children = RewriteLinks.fold_children(doc.children);
+  assert_eq!(doc.to_html(), "<p>SEE <a href=\"./docs.html\">THE <em>DOCS</em></a></p>\n");
+}
+
+#[test]
+fn borrowed_ast() {
+  use djot::ast::borrowed::{Document, Tag};
+  use std::borrow::Cow;
+
+  let source = "hi _there_ ` x `{#v} [l](/a\nb)\n\n[r]: /url\n";
+  let doc = Document::parse(source);
+  let borrowed = |it: &Cow<str>| matches!(it, Cow::Borrowed(_));
+  let Tag::Para(para) = &doc.children[0] else { panic!("{:?}", doc.children) };
+  let [Tag::Str(hi), Tag::Emph(_), Tag::Str(_), Tag::Verbatim(v), Tag::Str(_), Tag::Link(l)] =
+    para.children.as_slice()
+  else {
+    panic!("{:?}", para.children)
+  };
+  assert!(borrowed(&hi.text) && hi.text == "hi ");
+  assert!(borrowed(&v.text) && v.text == " x ");
+  assert!(borrowed(&v.attrs["id"]));
+  assert_eq!(l.destination.as_deref(), Some("/ab"));
+  assert!(borrowed(&doc.references["r"].destination));
+
+  assert_eq!(doc.into_owned().to_json(), djot::Document::parse(source).to_json());
+}
+
+#[test]
+fn to_djot() {
+  for_each_test_case(|file_stem, _, case| {
+    let doc = djot::Document::parse(&case.djot);
+    let djot = doc.to_djot();
+    // Escapes split text differently, so compare the HTML.
+    assert_eq!(
+      djot::Document::parse(&djot).to_html(),
+      doc.to_html(),
+      "{file_stem}:\n{}\nrendered as\n{djot}",
+      case.djot
+    );
+  });
+}
+
+#[test]
+fn block_attributes() {
+  let html = |src: &str| djot::Document::parse(src).to_html();
+  // Later values win, classes add up.
+  assert_eq!(
+    html("{#id}\n{key=val}\n{.foo .bar}\n{key=val2}\n{.baz}\n{#id2}\nOkay\n"),
+    "<p id=\"id2\" key=\"val2\" class=\"foo bar baz\">Okay</p>\n"
+  );
+  assert_eq!(html("{#id}\n# Heading\n"), "<section id=\"id\">\n<h1>Heading</h1>\n</section>\n");
+  assert_eq!(
+    html("{highlight=3}\n``` ruby\nx = 3\n```\n"),
+    "<pre highlight=\"3\"><code class=\"language-ruby\">x = 3\n</code></pre>\n"
+  );
+  assert_eq!(html("{% a comment\n  before a paragraph %}\n{}\nhi\n"), "<p>hi</p>\n");
+  assert_eq!(html("{#id .cla*ss*\n"), "<p>{#id .cla<strong>ss</strong></p>\n");
+
+  // Images get definition attributes too. Labels match ignoring differences
+  // in whitespace.
+  let doc = djot::Document::parse(
+    "{title=foo}\n[ref]: /url\n\n![img][ref] [Ref][]\n\n[a\nb][] [a b](/c)\n\n[a  b]: /ab\n",
+  );
+  assert_eq!(
+    doc.to_html(),
+    "<p><img alt=\"img\" src=\"/url\" title=\"foo\"> <a>Ref</a></p>\n\
+     <p><a href=\"/ab\">a\nb</a> <a href=\"/c\">a b</a></p>\n"
+  );
+  assert_eq!(doc.references["ref"].attrs["title"], "foo");
+  assert_eq!(doc.references["a b"].destination, "/ab");
+  assert_eq!(djot::Document::parse("# A\tb\n").references["A b"].destination, "#A-b");
+
+  // Only ids the parser wouldn't generate are written out.
+  let src = "{#x .y}\n# A\n\n# B\n\n{.c}\nd\n\n{title=\"e\"}\n[f]: /g\n";
+  let doc = djot::Document::parse(src);
+  assert_eq!(doc.to_djot(), src.replace("#x .y", ".y #x"));
+  assert_eq!(djot::Document::parse(&doc.to_djot()).to_json(), doc.to_json());
+
+  use djot::events::{Atom, Container, Event, Events};
+  let attrs = [("id".to_string(), "a".to_string()), ("class".to_string(), "b c".to_string())];
+  assert_eq!(
+    Events::parse("{#a .b}\n\n{.c}\nd").collect::<Vec<_>>(),
+    [
+      Event::Atom(Atom::Blankline, "\n"),
+      Event::Start(Container::Para, attrs.into_iter().collect()),
+      Event::Text("d"),
+      Event::End(Container::Para),
+    ]
+  );
+}
+
+#[test]
+fn diagnostics() {
+  let html = |src: &str| djot::Document::parse(src).to_html();
+  // Unclosed attributes are text.
+  assert_eq!(html("hi{#id .c\nand {more"), "<p>hi{#id .c\nand {more</p>\n");
+  assert_eq!(html("a{.b} c{.d"), "<p><span class=\"b\">a</span> c{.d</p>\n");
+
+  let check = |doc: &djot::Document| {
+    let diagnostics = doc.diagnostics();
+    let show = |it: &djot::Diagnostic| {
+      let djot::ast::SourceSpan { start, end } = it.span;
+      format!(
+        "{}:{}-{}:{} {}: {}",
+        start.line, start.col, end.line, end.col, it.severity, it.message
+      )
+    };
+    diagnostics.iter().map(show).collect::<Vec<_>>()
+  };
+  let src = "# Head\n\n[a][] [Head][] ![i][c] :+1: :nope: x{.y\n\n[c]: /c\n[c]: /d\n[u]: /u\n";
+  let mut doc = djot::Document::parse(src);
+  assert_eq!(
+    check(&doc),
+    [
+      "3:1-3:5 error: undefined reference `a`",
+      "3:29-3:35 warning: unknown emoji `:nope:`",
+      "3:37-3:37 warning: unclosed attributes",
+      "6:1-7:1 warning: reference `c` is already defined on line 5",
+      "7:1-8:1 hint: reference `u` is never used",
+    ]
+  );
+  doc.reparse(src.find("[a]").unwrap()..src.find("[a]").unwrap() + 5, "[u][]");
+  assert_eq!(check(&doc)[0], "3:29-3:35 warning: unknown emoji `:nope:`");
+  assert_eq!(check(&doc).len(), 3);
+
+  assert!(check(&djot::Document::parse("[a][]\n\n[a]: /a\n")).is_empty());
+  assert!(check(&djot::Document::from_json(&doc.to_json()).unwrap()).is_empty());
+}
+
+#[test]
+fn format() {
+  let opts = |width| djot::FormatOpts { width };
+  for_each_test_case(|file_stem, _, case| {
+    for width in [None, Some(20), Some(1)] {
+      let formatted = djot::format(&case.djot, &opts(width));
+      assert_eq!(
+        djot::format(&formatted, &opts(width)),
+        formatted,
+        "{file_stem}:\n{}\nformatted with width {width:?} as\n{formatted}",
+        case.djot
+      );
+    }
+  });
+}
+
+#[test]
+fn standalone() {
+  use djot::template::Template;
+
+  let template = Template::parse(
+    "<title>$title$</title>\n$for(css)$\n<link href=\"$css$\">\n$endfor$\n\
+     $if(toc)$\n$toc$\n$else$\nno toc $$\n$endif$\n$body$",
+  )
+  .unwrap();
+  let standalone = djot::Standalone {
+    template,
+    title: Some("a < b".to_string()),
+    css: vec!["x.css".to_string(), "y\".css".to_string()],
+    ..djot::Standalone::default()
+  };
+  let opts = djot::HtmlOpts { standalone: Some(standalone), ..djot::HtmlOpts::default() };
+  assert_eq!(
+    djot::Document::parse("*hi*").to_html_opts(&opts),
+    "<title>a &lt; b</title>\n<link href=\"x.css\">\n<link href=\"y&quot;.css\">\n\
+     no toc $\n<p><strong>hi</strong></p>\n"
+  );
+
+  let opts =
+    djot::HtmlOpts { standalone: Some(djot::Standalone::default()), ..djot::HtmlOpts::default() };
+  let page = djot::Document::parse("hi").to_html_opts(&opts);
+  assert!(page.starts_with("<!DOCTYPE html>\n<html>\n"), "{page}");
+  assert!(page.contains("<body>\n<p>hi</p>\n"), "{page}");
+  assert!(!page.contains("<title>") && !page.contains('$'), "{page}");
+
+  for bad in ["$if(a)$", "$endif$", "$a b$", "$for(a)$$endif$", "$a"] {
+    assert!(Template::parse(bad).is_err(), "{bad}");
+  }
+}
+
+#[test]
+fn headings() {
+  let doc = djot::Document::parse(
+    "# Intro\n\nSee [Intro][] and [Usage][].\n\n## Usage, maybe?\n\n# Intro\n\n#\n\n[Usage]: /usage\n",
+  );
+  assert_eq!(
+    doc.to_html(),
+    "<section id=\"Intro\">\n<h1>Intro</h1>\n\
+     <p>See <a href=\"#Intro\">Intro</a> and <a href=\"/usage\">Usage</a>.</p>\n\
+     <section id=\"Usage-maybe\">\n<h2>Usage, maybe?</h2>\n</section>\n</section>\n\
+     <section id=\"Intro-1\">\n<h1>Intro</h1>\n</section>\n\
+     <section id=\"s-1\">\n<h1></h1>\n</section>\n"
+  );
+  // The first heading wins, explicit definitions win over headings.
+  assert_eq!(doc.references["Intro"].destination, "#Intro");
+  assert_eq!(doc.references["Usage, maybe?"].destination, "#Usage-maybe");
+  assert_eq!(doc.references["Usage"].destination, "/usage");
+  assert_eq!(doc.to_djot().matches("]: ").count(), 1, "{}", doc.to_djot());
+  assert_eq!(doc.to_text(), "Intro\n\nSee Intro and Usage.\n\nUsage, maybe?\n\nIntro\n\n");
+
+  // Ids and references take the text of all the inlines.
+  let doc = djot::Document::parse("# *Important*\n\n# Using `cargo`\n\n# A _b_ c\n");
+  let ids: Vec<_> = doc.toc().into_iter().map(|it| it.id).collect();
+  assert_eq!(ids, ["Important", "Using-cargo", "A-b-c"]);
+  assert_eq!(doc.references["A b c"].destination, "#A-b-c");
+
+  let toc = |level, id: &str, text: &str, children| djot::TocEntry {
+    level,
+    id: id.to_string(),
+    text: text.to_string(),
+    children,
+  };
+  let doc = djot::Document::parse("# _A_ & b\n\n### C\n\n## D\n\n# E\n");
+  assert_eq!(
+    doc.toc(),
+    vec![
+      toc(1, "A-b", "A & b", vec![toc(3, "C", "C", vec![]), toc(2, "D", "D", vec![])]),
+      toc(1, "E", "E", vec![]),
+    ]
+  );
+  let opts = djot::HtmlOpts { toc: Some(2), ..djot::HtmlOpts::default() };
+  let html = doc.to_html_opts(&opts);
+  assert!(
+    html.starts_with(
+      "<nav id=\"toc\">\n<ul>\n<li><a href=\"#A-b\">A &amp; b</a>\n\
+       <ul>\n<li><a href=\"#D\">D</a></li>\n</ul>\n</li>\n\
+       <li><a href=\"#E\">E</a></li>\n</ul>\n</nav>\n<section id=\"A-b\">\n"
+    ),
+    "{html}"
+  );
+  let opts = djot::HtmlOpts { toc: Some(3), ..djot::HtmlOpts::default() };
+  assert_eq!(djot::Document::parse("text").to_html_opts(&opts), "<p>text</p>\n");
+
+  let mut doc = djot::Document::parse("# A\n\n## B\n\ntext\n\n# C\n");
+  doc.reparse(11..11, "# D\n\n");
+  let text = "# A\n\n## B\n\n# D\n\ntext\n\n# C\n";
+  assert_eq!(doc.to_json(), djot::Document::parse(text).to_json());
+}
+
+#[test]
+fn metadata() {
+  let source = "---\ntitle: Notes # draft\nauthors:\n  - Ann\n  - 'Bo, Jr.'\n\
+                tags: [a, b]\ndraft: true\nyear: 2024\nsubtitle: \"x: \\\"y\\\"\"\n---\nhi\n";
+  let doc = djot::Document::parse(source);
+  let json = serde_json::to_value(&doc.metadata).unwrap();
+  assert_eq!(
+    json,
+    serde_json::json!({
+      "title": "Notes",
+      "authors": ["Ann", "Bo, Jr."],
+      "tags": ["a", "b"],
+      "draft": true,
+      "year": 2024,
+      "subtitle": "x: \"y\"",
+    })
+  );
+  assert_eq!(doc.to_html(), "<p>hi</p>\n");
+
+  let roundtrip = djot::Document::from_json(&doc.to_json()).unwrap();
+  assert_eq!(roundtrip.metadata, doc.metadata);
+  let roundtrip = djot::Document::parse(&doc.to_djot());
+  assert_eq!(roundtrip.to_json(), doc.to_json(), "{}", doc.to_djot());
+
+  let opts = djot::HtmlOpts {
+    standalone: Some(djot::Standalone {
+      template: djot::template::Template::parse("$title$|$for(authors)$<$authors$>$endfor$")
+        .unwrap(),
+      ..djot::Standalone::default()
+    }),
+    ..djot::HtmlOpts::default()
+  };
+  assert_eq!(doc.to_html_opts(&opts), "Notes|<Ann><Bo, Jr.>");
+
+  let opts = djot::ParseOpts { source_positions: true, ..djot::ParseOpts::default() };
+  let doc = djot::Document::parse_opts(opts, "---\na: 1\n---\nhi\n");
+  let djot::ast::Tag::Para(para) = &doc.children[0] else { panic!() };
+  assert_eq!(para.pos.unwrap().start.line, 4);
+
+  // Not the supported subset, or not closed: plain djot.
+  for source in ["---\na:\n  b: c\n---\n", "---\na: b\n", "x\n---\na: b\n---\n"] {
+    let doc = djot::Document::parse(source);
+    assert!(doc.metadata.is_empty() && !doc.children.is_empty(), "{source}");
+  }
+
+  let mut doc = djot::Document::parse("---\na: 1\n---\nhi\n");
+  doc.reparse(7..8, "2");
+  assert_eq!(doc.metadata["a"], 2);
+  doc.reparse(13..15, "ho");
+  assert_eq!((doc.metadata["a"].clone(), doc.to_html()), (2.into(), "<p>ho</p>\n".to_string()));
+  doc.reparse(0..0, "x");
+  assert!(doc.metadata.is_empty());
+  let mut doc = djot::Document::parse("---\na: 1\n\nhi\n");
+  doc.reparse(9..9, "---");
+  assert_eq!(doc.metadata["a"], 1);
+}
+
+#[test]
+fn highlight() {
+  use std::sync::Arc;
+
+  let src = "``` rust\nlet x = 1;\n```\n\n```\n<a>\n```\n";
+  let highlighter = |lang: &str, code: &str| Some(format!("{lang}: {}", code.len()));
+  let opts =
+    djot::HtmlOpts { highlighter: Some(Arc::new(highlighter)), ..djot::HtmlOpts::default() };
+  assert_eq!(
+    djot::Document::parse(src).to_html_opts(&opts),
+    "<pre><code class=\"language-rust\">rust: 11</code></pre>\n<pre><code>&lt;a&gt;\n</code></pre>\n"
+  );
+
+  #[cfg(feature = "highlight")]
+  {
+    let opts = djot::HtmlOpts {
+      highlighter: Some(Arc::new(djot::highlight::Builtin)),
+      ..djot::HtmlOpts::default()
+    };
+    let html = djot::Document::parse(src).to_html_opts(&opts);
+    assert!(
+      html.contains(
+        "<span class=\"tok-keyword\">let</span> x = <span class=\"tok-number\">1</span>;"
+      ),
+      "{html}"
+    );
+    let html = djot::Document::parse("``` py\n<a>\n```\n").to_html_opts(&opts);
+    assert!(html.contains(">&lt;a&gt;\n</code>"), "{html}");
+    let html = djot::Document::parse("``` cobol\n<a>\n```\n").to_html_opts(&opts);
+    assert!(html.contains(">&lt;a&gt;\n</code>"), "{html}");
+
+    let cases = [
+      ("rust", "#[test]\nfn f<'a>() -> u8 { m!('x') } // c", "<span class=\"tok-meta\">#[test]</span>\n<span class=\"tok-keyword\">fn</span> f&lt;<span class=\"tok-labelName\">'a</span>&gt;() -&gt; <span class=\"tok-typeName\">u8</span> { <span class=\"tok-macroName\">m!</span>(<span class=\"tok-string\">'x'</span>) } <span class=\"tok-comment\">// c</span>"),
+      ("Python", "def f(): return None # c", "<span class=\"tok-keyword\">def</span> f(): <span class=\"tok-keyword\">return</span> <span class=\"tok-atom\">None</span> <span class=\"tok-comment\"># c</span>"),
+      ("json", "{\"a\": [true, \"b\"]}", "{<span class=\"tok-propertyName\">&quot;a&quot;</span>: [<span class=\"tok-bool\">true</span>, <span class=\"tok-string\">&quot;b&quot;</span>]}"),
+      ("bash", "X=1; echo \"$X\" $Y#z", "<span class=\"tok-variableName\">X</span>=1; echo <span class=\"tok-string\">&quot;$X&quot;</span> <span class=\"tok-variableName\">$Y</span>#z"),
+      ("toml", "[a]\nb = 1 # c", "<span class=\"tok-heading\">[a]</span>\n<span class=\"tok-propertyName\">b</span> = <span class=\"tok-number\">1</span> <span class=\"tok-comment\"># c</span>"),
+      ("djot", "# *a* b\n", "<span class=\"tok-heading\"># </span><span class=\"tok-strong\">*a*</span><span class=\"tok-heading\"> b</span>\n"),
+    ];
+    for (lang, code, want) in cases {
+      use djot::highlight::Highlighter;
+      assert_eq!(djot::highlight::Builtin.highlight(lang, code).unwrap(), want, "{lang}");
+    }
+  }
+}
+
+#[test]
+fn reparse() {
+  // xorshift, to keep the test reproducible without extra dependencies.
+  let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+  let mut random = |n: usize| {
+    seed ^= seed << 13;
+    seed ^= seed >> 7;
+    seed ^= seed << 17;
+    seed as usize % n
+  };
+  let snippets = [
+    "\n",
+    "\n\n",
+    "a",
+    " ",
+    "*",
+    "_",
+    "`",
+    "```\n",
+    "[r]: /url\n",
+    "[r]",
+    ":nope:",
+    "](b)",
+    "{#x}",
+    "{.c}\n",
+    "{#x}\n# h\n",
+    "й",
+  ];
+  let opts = djot::ParseOpts { source_positions: true, ..djot::ParseOpts::default() };
+
+  // The cases of each file, one after the other.
+  let mut files: Vec<(String, String)> = Vec::new();
+  for_each_test_case(|file_stem, i, case| {
+    if i == 0 {
+      files.push((file_stem.to_string(), String::new()));
+    }
+    files.last_mut().unwrap().1.push_str(&(case.djot + "\n"));
+  });
+  for (file_stem, mut text) in files {
+    let mut doc = djot::Document::parse_opts(opts.clone(), &text);
+    for _ in 0..200 {
+      let floor = |idx: usize| (0..=idx).rev().find(|&it| text.is_char_boundary(it)).unwrap();
+      let start = floor(random(text.len() + 1));
+      let edit = start..floor((start + random(20)).min(text.len())).max(start);
+      let new_text: String = (0..random(3)).map(|_| snippets[random(snippets.len())]).collect();
+
+      let before = text.clone();
+      text.replace_range(edit.clone(), &new_text);
+      doc.reparse(edit.clone(), &new_text);
+      let want = djot::Document::parse_opts(opts.clone(), &text);
+      assert_eq!(
+        doc.to_json(),
+        want.to_json(),
+        "{file_stem}: replacing {edit:?} of\n{before:?}\nwith {new_text:?}"
+      );
+      assert_eq!(doc.diagnostics(), want.diagnostics(), "{file_stem}: diagnostics");
+    }
+  }
+
+  // The text kept for reparsing mustn't stop documents from crossing threads.
+  fn assert_send_sync<T: Send + Sync>() {}
+  assert_send_sync::<djot::Document>();
+}
+
+#[test]
+fn parallel() {
+  let mut text = String::new();
+  for_each_test_case(|_, _, case| {
+    text.push_str(&case.djot);
+    text.push('\n');
+  });
+  let text = text.repeat(20);
+
+  let opts = |parallel| djot::ParseOpts {
+    debug_matches: true,
+    source_positions: true,
+    parallel,
+    ..djot::ParseOpts::default()
+  };
+  let serial = djot::Document::parse_opts(opts(false), &text);
+  for _ in 0..3 {
+    let doc = djot::Document::parse_opts(opts(true), &text);
+    assert!(doc.debug == serial.debug, "match streams differ");
+    assert!(doc.to_json() == serial.to_json(), "trees differ");
+  }
+
+  let opts = |parallel| djot::ParseOpts { parallel, ..djot::ParseOpts::default() };
+  let edit = text.len() / 2..text.len() / 2;
+  let mut doc = djot::Document::parse_opts(opts(true), &text);
+  let mut serial = djot::Document::parse_opts(opts(false), &text);
+  doc.reparse(edit.clone(), "\n\n_a_ *b*\n\n");
+  serial.reparse(edit, "\n\n_a_ *b*\n\n");
+  assert!(doc.to_json() == serial.to_json(), "reparsed trees differ");
 }
 
 #[derive(Debug, Default)]
 struct TestCase {
   djot: String,
   html: String,
+  matches: bool,
 }
 
 #[derive(Debug)]
@@ -87,6 +744,23 @@ enum ParseState {
   Html(TestCase, usize),
 }
 
+/// Calls `f` with the file stem, the index and the contents of every case in
+/// `tests/data`, going through the files by name.
+fn for_each_test_case(mut f: impl FnMut(&str, usize, TestCase)) {
+  let mut paths: Vec<_> =
+    fs::read_dir("./tests/data").unwrap().map(|it| it.unwrap().path()).collect();
+  paths.sort();
+  for path in paths {
+    if path.extension().unwrap_or_default() != "test" {
+      continue;
+    }
+    let file_stem = path.file_stem().unwrap().to_str().unwrap();
+    for (i, case) in parse_test(&fs::read_to_string(&path).unwrap()).into_iter().enumerate() {
+      f(file_stem, i, case)
+    }
+  }
+}
+
 fn parse_test(source: &str) -> Vec<TestCase> {
   let mut res = Vec::new();
   let mut state = ParseState::Init;
@@ -96,7 +770,10 @@ fn parse_test(source: &str) -> Vec<TestCase> {
         break;
       }
       ParseState::Init => match parse_fence(line) {
-        Some(fence) => ParseState::Djot(TestCase::default(), fence),
+        Some(fence) => {
+          let matches = line[fence..].trim() == "[matches]";
+          ParseState::Djot(TestCase { matches, ..TestCase::default() }, fence)
+        }
         None => ParseState::Init,
       },
       ParseState::Djot(mut test_case, test_case_fence) => {
@@ -126,8 +803,9 @@ fn parse_test(source: &str) -> Vec<TestCase> {
 }
 
 fn parse_fence(line: &str) -> Option<usize> {
-  if line.bytes().all(|it| it == b'`') && line.len() > 0 {
-    Some(line.len())
+  let (fence, options) = line.split_once(' ').unwrap_or((line, ""));
+  if fence.bytes().all(|it| it == b'`') && !fence.is_empty() && !options.contains('`') {
+    Some(fence.len())
   } else {
     None
   }
@@ -159,7 +837,7 @@ impl LastFail {
   fn test_ok(&mut self) {
     if let Some((name, pos)) = &self.loaded {
       eprintln!("{}:{} is now ok!", name, pos);
-      let _ = fs::remove_file(&fail_file());
+      let _ = fs::remove_file(fail_file());
       self.loaded = None;
     }
     self.current = None